@@ -0,0 +1,69 @@
+//! Per-call cost of rendering tool templates.
+//!
+//! Run with `cargo bench --bench template`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use serde_json::json;
+use std::hint::black_box;
+
+// The crate is a binary, so the template module is compiled into the benchmark directly
+#[allow(dead_code)]
+#[path = "../src/core/template.rs"]
+mod template;
+
+use template::{Template, TemplateMode};
+
+const URL: &str = "https://api.example.com/search?q={ input.query | url_encode }&limit={ input.limit }&page={ input.page }";
+const BODY: &str = r#"{"query": "{ input.query }", "filters": {"tags": { input.tags }, "owner": "{ input.owner }"}}"#;
+
+const FULL_BODY: &str = r#"{"query": {{ input.query | json }}, "filters": {"tags": [{% for tag in input.tags %}"{{ tag | lower }}"{% if not loop.last %}, {% endif %}{% endfor %}]{% if input.owner %}, "owner": "{{ input.owner }}"{% endif %}}}"#;
+
+fn templates() -> Template {
+    let mut template = Template::new(TemplateMode::SIMPLE);
+    template.add_template("url", URL).unwrap();
+    template.add_template("body", BODY).unwrap();
+    template
+}
+
+fn full_templates() -> Template {
+    let mut template = Template::new(TemplateMode::FULL);
+    template.add_template("body", FULL_BODY).unwrap();
+    template
+}
+
+fn render(c: &mut Criterion) {
+    let template = templates();
+    let full_template = full_templates();
+    let context = json!({
+        Template::INPUT_NAME: {
+            "query": "rust \"template\" engines",
+            "limit": 20,
+            "page": 3,
+            "tags": ["mcp", "bench"],
+            "owner": "easymcp",
+        }
+    });
+
+    c.bench_function("render url", |b| {
+        b.iter(|| template.render("url", black_box(&context)).unwrap())
+    });
+    c.bench_function("render body", |b| {
+        b.iter(|| template.render("body", black_box(&context)).unwrap())
+    });
+    c.bench_function("render full body", |b| {
+        b.iter(|| full_template.render("body", black_box(&context)).unwrap())
+    });
+}
+
+fn compile(c: &mut Criterion) {
+    c.bench_function("compile url and body", |b| {
+        b.iter(|| black_box(templates()))
+    });
+    c.bench_function("clone compiled templates", |b| {
+        let template = templates();
+        b.iter(|| black_box(template.clone()))
+    });
+}
+
+criterion_group!(benches, render, compile);
+criterion_main!(benches);
@@ -0,0 +1,276 @@
+use crate::core::config::CacheConfig;
+use duration_string::DurationString;
+use rmcp::model::CallToolResult;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CacheEntry {
+    expires_at: u64,
+    result: CallToolResult,
+    #[serde(skip)]
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, CacheEntry>,
+    tick: u64,
+}
+
+/// In-memory LRU cache of successful tool results, optionally backed by a directory on disk.
+pub struct ResponseCache {
+    tool_name: String,
+    ttl: Duration,
+    max_entries: usize,
+    respect_cache_control: bool,
+    disk_path: Option<PathBuf>,
+    state: Mutex<CacheState>,
+}
+
+impl ResponseCache {
+    const DEFAULT_MAX_ENTRIES: usize = 100;
+
+    pub fn new(tool_name: String, config: CacheConfig) -> Self {
+        let ttl: Duration = DurationString::from_string(config.ttl.clone())
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Invalid cache ttl {} for tool {}: {}",
+                    config.ttl, tool_name, err
+                )
+            })
+            .into();
+
+        Self {
+            tool_name,
+            ttl,
+            max_entries: config
+                .max_entries
+                .unwrap_or(Self::DEFAULT_MAX_ENTRIES)
+                .max(1),
+            respect_cache_control: config.respect_cache_control.unwrap_or(true),
+            disk_path: config.disk_path.map(PathBuf::from),
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Derives a cache key from the rendered parts of a request.
+    pub fn key(parts: &[&str]) -> String {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part.as_bytes());
+            hasher.update([0u8]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+
+    fn disk_entry_path(&self, key: &str) -> Option<PathBuf> {
+        self.disk_path
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", key)))
+    }
+
+    /// Returns how long a response may be cached, honoring `Cache-Control` when enabled.
+    ///
+    /// `None` means the response must not be cached.
+    pub fn ttl_for(&self, cache_control: Option<&str>) -> Option<Duration> {
+        let mut ttl = self.ttl;
+
+        if self.respect_cache_control
+            && let Some(cache_control) = cache_control
+        {
+            for directive in cache_control
+                .split(',')
+                .map(|value| value.trim().to_lowercase())
+            {
+                if directive == "no-store" || directive == "no-cache" {
+                    return None;
+                }
+                if let Some(max_age) = directive.strip_prefix("max-age=")
+                    && let Ok(max_age) = max_age.trim_matches('"').parse::<u64>()
+                {
+                    ttl = ttl.min(Duration::from_secs(max_age));
+                }
+            }
+        }
+
+        (!ttl.is_zero()).then_some(ttl)
+    }
+
+    pub async fn get(&self, key: &str) -> Option<CallToolResult> {
+        let now = Self::now();
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.tick += 1;
+            let tick = state.tick;
+            match state.entries.get_mut(key) {
+                Some(entry) if entry.expires_at > now => {
+                    entry.last_used = tick;
+                    tracing::info!(tool = %self.tool_name, key, "cache hit");
+                    return Some(entry.result.clone());
+                }
+                Some(_) => {
+                    state.entries.remove(key);
+                }
+                None => (),
+            }
+        }
+
+        if let Some(path) = self.disk_entry_path(key)
+            && let Ok(bytes) = tokio::fs::read(&path).await
+        {
+            match serde_json::from_slice::<CacheEntry>(&bytes) {
+                Ok(entry) if entry.expires_at > now => {
+                    tracing::info!(tool = %self.tool_name, key, "disk cache hit");
+                    let result = entry.result.clone();
+                    self.insert_entry(key.to_string(), entry);
+                    return Some(result);
+                }
+                _ => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+            }
+        }
+
+        tracing::info!(tool = %self.tool_name, key, "cache miss");
+        None
+    }
+
+    fn insert_entry(&self, key: String, mut entry: CacheEntry) {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        entry.last_used = state.tick;
+
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.max_entries {
+            let least_recently_used = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recently_used) = least_recently_used {
+                state.entries.remove(&least_recently_used);
+            }
+        }
+
+        state.entries.insert(key, entry);
+    }
+
+    /// Stores a result for `ttl`. Error results are never cached.
+    pub async fn insert(&self, key: &str, result: &CallToolResult, ttl: Duration) {
+        if result.is_error == Some(true) {
+            return;
+        }
+
+        let entry = CacheEntry {
+            expires_at: Self::now() + ttl.as_secs().max(1),
+            result: result.clone(),
+            last_used: 0,
+        };
+
+        if let Some(path) = self.disk_entry_path(key) {
+            let written = match serde_json::to_vec(&entry) {
+                Ok(bytes) => match path.parent() {
+                    Some(dir) => match tokio::fs::create_dir_all(dir).await {
+                        Ok(()) => tokio::fs::write(&path, bytes).await,
+                        Err(err) => Err(err),
+                    },
+                    None => tokio::fs::write(&path, bytes).await,
+                },
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = written {
+                tracing::warn!(tool = %self.tool_name, error = %err, "failed to write cache entry to disk");
+            }
+        }
+
+        self.insert_entry(key.to_string(), entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::Content;
+    use serde_json::json;
+
+    fn response_cache(config: serde_json::Value) -> ResponseCache {
+        ResponseCache::new("tool".to_string(), serde_json::from_value(config).unwrap())
+    }
+
+    fn result(text: &str) -> CallToolResult {
+        CallToolResult::success(vec![Content::text(text)])
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("easymcp-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entry_is_evicted() {
+        let cache = response_cache(json!({"ttl": "1m", "max_entries": 2}));
+        let ttl = Duration::from_secs(60);
+        cache.insert("a", &result("a"), ttl).await;
+        cache.insert("b", &result("b"), ttl).await;
+        assert!(cache.get("a").await.is_some());
+
+        cache.insert("c", &result("c"), ttl).await;
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("c").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn entries_persist_on_disk_across_caches() {
+        let dir = temp_dir("cache-persist");
+        let config = json!({"ttl": "1m", "disk_path": dir});
+        response_cache(config.clone())
+            .insert("key", &result("cached"), Duration::from_secs(60))
+            .await;
+
+        let restored = response_cache(config).get("key").await.unwrap();
+        assert_eq!(restored.content, result("cached").content);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn error_results_are_not_cached() {
+        let dir = temp_dir("cache-errors");
+        let cache = response_cache(json!({"ttl": "1m", "disk_path": dir}));
+        let error = CallToolResult::error(vec![Content::text("failed")]);
+        cache.insert("key", &error, Duration::from_secs(60)).await;
+
+        assert!(cache.get("key").await.is_none());
+        assert!(!dir.join("key.json").exists());
+    }
+
+    #[test]
+    fn cache_control_shortens_or_disables_the_ttl() {
+        let cache = response_cache(json!({"ttl": "1m"}));
+        assert_eq!(cache.ttl_for(None), Some(Duration::from_secs(60)));
+        assert_eq!(
+            cache.ttl_for(Some("public, max-age=5")),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(cache.ttl_for(Some("no-store")), None);
+        assert_eq!(cache.ttl_for(Some("max-age=0")), None);
+
+        let cache = response_cache(json!({"ttl": "1m", "respect_cache_control": false}));
+        assert_eq!(
+            cache.ttl_for(Some("no-store")),
+            Some(Duration::from_secs(60))
+        );
+    }
+}
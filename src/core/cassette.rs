@@ -0,0 +1,405 @@
+use crate::core::cache::ResponseCache;
+use crate::core::config::{CassetteConfig, CassetteMode};
+use crate::core::context::TemplateContext;
+use crate::core::http::RenderedHttpRequest;
+use crate::core::output::BoundedOutput;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use reqwest::header::HeaderMap;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const REDACTED: &str = "[REDACTED]";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    body: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    body_base64: Option<String>,
+    /// Whether the body was cut at the recording limit
+    #[serde(default)]
+    truncated: bool,
+}
+
+/// One upstream request and the response it got.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+/// Records upstream HTTP exchanges to a directory, or answers requests from it without network.
+///
+/// Each exchange is a JSON file named after the method, URL and body of its request.
+pub struct Cassette {
+    mode: CassetteMode,
+    dir: PathBuf,
+    template_context: Arc<TemplateContext>,
+}
+
+impl Cassette {
+    pub fn new(config: CassetteConfig, template_context: Arc<TemplateContext>) -> Self {
+        Self {
+            mode: config.mode,
+            dir: PathBuf::from(config.dir),
+            template_context,
+        }
+    }
+
+    /// Headers that carry credentials, recorded as `[REDACTED]`.
+    fn is_secret_header(name: &str) -> bool {
+        let name = name.to_lowercase();
+        matches!(name.as_str(), "cookie" | "set-cookie")
+            || ["auth", "token", "secret", "key", "password", "session"]
+                .iter()
+                .any(|part| name.contains(part))
+    }
+
+    fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        self.template_context.redact(&mut text);
+        text
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = match Self::is_secret_header(name.as_str()) {
+                    true => REDACTED.to_string(),
+                    false => self.redact(value.to_str().unwrap_or("")),
+                };
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn recorded_request(&self, request: &RenderedHttpRequest, url: &str) -> RecordedRequest {
+        RecordedRequest {
+            method: format!("{:?}", request.method),
+            url: self.redact(url),
+            headers: self.headers(&request.headers).into_iter().collect(),
+            body: request.body.as_deref().map(|body| self.redact(body)),
+        }
+    }
+
+    fn entry_path(&self, request: &RecordedRequest) -> PathBuf {
+        let key = ResponseCache::key(&[
+            &request.method,
+            &request.url,
+            request.body.as_deref().unwrap_or(""),
+        ]);
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Sends `builder`, the request to `url`, or replays the response recorded for it.
+    ///
+    /// When recording, at most one byte past `body_limit` is read from the body, so the caller
+    /// still sees a body over its limit as truncated.
+    pub async fn send(
+        &self,
+        request: &RenderedHttpRequest,
+        url: &str,
+        builder: reqwest::RequestBuilder,
+        body_limit: Option<u64>,
+    ) -> Result<reqwest::Response, String> {
+        let recorded_request = self.recorded_request(request, url);
+        let path = self.entry_path(&recorded_request);
+
+        match self.mode {
+            CassetteMode::REPLAY => {
+                let interaction = tokio::fs::read(&path)
+                    .await
+                    .ok()
+                    .and_then(|bytes| serde_json::from_slice::<Interaction>(&bytes).ok())
+                    .ok_or_else(|| {
+                        format!(
+                            "No recorded response for this {} request in {}",
+                            recorded_request.method,
+                            self.dir.display()
+                        )
+                    })?;
+                Self::response(interaction.response)
+            }
+            CassetteMode::RECORD => {
+                let mut response = builder.send().await.map_err(|err| err.to_string())?;
+                let status = response.status().as_u16();
+                let mut headers = response.headers().clone();
+                let mut body = BoundedOutput::default();
+                while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+                    if body.push(&chunk, body_limit.map(|limit| limit + 1)) {
+                        break;
+                    }
+                }
+                let BoundedOutput { data, truncated } = body;
+
+                // The body may be redacted, so its framing no longer applies. A cut body keeps
+                // its length, which tells the caller the original size.
+                headers.remove(reqwest::header::TRANSFER_ENCODING);
+                headers.remove(reqwest::header::CONNECTION);
+                if !truncated {
+                    headers.remove(reqwest::header::CONTENT_LENGTH);
+                }
+
+                let (body, body_base64) = match std::str::from_utf8(&data) {
+                    Ok(text) => (Some(self.redact(text)), None),
+                    Err(_) => (None, Some(BASE64_STANDARD.encode(&data))),
+                };
+                let interaction = Interaction {
+                    request: recorded_request,
+                    response: RecordedResponse {
+                        status,
+                        headers: self.headers(&headers),
+                        body,
+                        body_base64,
+                        truncated,
+                    },
+                };
+
+                let written = match serde_json::to_vec_pretty(&interaction) {
+                    Ok(bytes) => match tokio::fs::create_dir_all(&self.dir).await {
+                        Ok(()) => tokio::fs::write(&path, bytes).await,
+                        Err(err) => Err(err),
+                    },
+                    Err(err) => Err(err.into()),
+                };
+                if let Err(err) = written {
+                    tracing::warn!(error = %err, "failed to write recorded response to disk");
+                }
+
+                // The caller reads the response it would have got, not the redacted recording
+                let mut replayed = http::Response::builder().status(status);
+                for (name, value) in headers.iter() {
+                    replayed = replayed.header(name, value);
+                }
+                replayed
+                    .body(data)
+                    .map(reqwest::Response::from)
+                    .map_err(|err| err.to_string())
+            }
+        }
+    }
+
+    fn response(recorded: RecordedResponse) -> Result<reqwest::Response, String> {
+        let body = match (recorded.body, recorded.body_base64) {
+            (Some(body), _) => body.into_bytes(),
+            (None, Some(encoded)) => BASE64_STANDARD
+                .decode(encoded)
+                .map_err(|err| format!("Invalid recorded body: {}", err))?,
+            (None, None) => vec![],
+        };
+
+        let mut response = http::Response::builder().status(recorded.status);
+        for (name, value) in recorded.headers.iter() {
+            response = response.header(name, value);
+        }
+        response
+            .body(body)
+            .map(reqwest::Response::from)
+            .map_err(|err| format!("Invalid recorded response: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::{DynamicMCPConfig, HttpMethod};
+    use reqwest::header::HeaderValue;
+    use std::io::{Read, Write};
+
+    /// Answers one request on a local port with `body`, setting a cookie.
+    fn serve(body: &'static str) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 8192];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nset-cookie: session=abc\r\nx-request-id: 7\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            );
+        });
+        url
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("easymcp-{}-{}", name, std::process::id()))
+    }
+
+    /// A template context with one secret, `s3cr3t`.
+    fn template_context(name: &str) -> Arc<TemplateContext> {
+        let secret_path = temp_dir(&format!("{}-secret", name));
+        std::fs::write(&secret_path, "s3cr3t").unwrap();
+        let config: DynamicMCPConfig = serde_yaml::from_str(&format!(
+            "tools: []\nsecrets:\n  token: {}\n",
+            secret_path.display()
+        ))
+        .unwrap();
+        let template_context = Arc::new(TemplateContext::new(&config));
+        std::fs::remove_file(&secret_path).unwrap();
+        template_context
+    }
+
+    fn cassette(mode: CassetteMode, dir: &std::path::Path, name: &str) -> Cassette {
+        Cassette::new(
+            CassetteConfig {
+                mode,
+                dir: dir.to_string_lossy().to_string(),
+            },
+            template_context(name),
+        )
+    }
+
+    fn request(url: &str) -> RenderedHttpRequest {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer s3cr3t"));
+        headers.insert("x-trace", HeaderValue::from_static("trace-s3cr3t"));
+        RenderedHttpRequest {
+            method: HttpMethod::POST,
+            url: url.to_string(),
+            headers,
+            body: Some(r#"{"q": "rust", "token": "s3cr3t"}"#.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_responses_are_redacted_and_replayed() {
+        let dir = temp_dir("cassette-record");
+        let url = serve(r#"{"echo": "s3cr3t"}"#);
+        let request = request(&url);
+        let client = reqwest::Client::new();
+
+        let recorder = cassette(CassetteMode::RECORD, &dir, "cassette-record");
+        let builder = client.post(&url).body(request.body.clone().unwrap());
+        let response = recorder.send(&request, &url, builder, None).await.unwrap();
+        assert_eq!(response.status(), 200);
+        // The caller gets the response as the upstream sent it
+        assert_eq!(response.text().await.unwrap(), r#"{"echo": "s3cr3t"}"#);
+
+        let entries: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        let recorded = std::fs::read_to_string(&entries[0]).unwrap();
+        assert!(!recorded.contains("s3cr3t"), "{}", recorded);
+        let interaction: Interaction = serde_json::from_str(&recorded).unwrap();
+        assert_eq!(interaction.request.headers["authorization"], REDACTED);
+        assert_eq!(interaction.request.headers["x-trace"], "trace-[REDACTED]");
+        assert_eq!(
+            interaction.request.body.as_deref(),
+            Some(r#"{"q": "rust", "token": "[REDACTED]"}"#)
+        );
+        let response_headers: BTreeMap<String, String> =
+            interaction.response.headers.into_iter().collect();
+        assert_eq!(response_headers["set-cookie"], REDACTED);
+        assert_eq!(response_headers["x-request-id"], "7");
+        assert!(!response_headers.contains_key("content-length"));
+        assert_eq!(
+            interaction.response.body.as_deref(),
+            Some(r#"{"echo": "[REDACTED]"}"#)
+        );
+
+        // Replaying never touches the network, the upstream has already gone away
+        let player = cassette(CassetteMode::REPLAY, &dir, "cassette-replay");
+        let builder = client.post(&url);
+        let response = player.send(&request, &url, builder, None).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["x-request-id"], "7");
+        assert_eq!(response.text().await.unwrap(), r#"{"echo": "[REDACTED]"}"#);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recording_stops_reading_past_the_body_limit() {
+        let dir = temp_dir("cassette-limit");
+        let url = serve("0123456789abcdefghij");
+        let request = request(&url);
+
+        let recorder = cassette(CassetteMode::RECORD, &dir, "cassette-limit");
+        let builder = reqwest::Client::new().post(&url);
+        let response = recorder
+            .send(&request, &url, builder, Some(8))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-length"], "20");
+        assert_eq!(response.text().await.unwrap(), "012345678");
+
+        let entry = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let interaction: Interaction =
+            serde_json::from_slice(&std::fs::read(entry.path()).unwrap()).unwrap();
+        assert!(interaction.response.truncated);
+        assert_eq!(interaction.response.body.as_deref(), Some("012345678"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replaying_an_unrecorded_request_fails() {
+        let dir = temp_dir("cassette-missing");
+        let player = cassette(CassetteMode::REPLAY, &dir, "cassette-missing");
+        let url = "http://127.0.0.1:9/missing";
+        let builder = reqwest::Client::new().get(url);
+        let err = player
+            .send(&request(url), url, builder, None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            format!(
+                "No recorded response for this POST request in {}",
+                dir.display()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_bodies_are_replayed_from_base64() {
+        let response = Cassette::response(RecordedResponse {
+            status: 201,
+            headers: vec![("content-type".to_string(), "image/png".to_string())],
+            body: None,
+            body_base64: Some(BASE64_STANDARD.encode([0x89, b'P', b'N', b'G', 0xff])),
+            truncated: false,
+        })
+        .unwrap();
+        assert_eq!(response.status(), 201);
+        assert_eq!(response.headers()["content-type"], "image/png");
+        assert_eq!(
+            response.bytes().await.unwrap().as_ref(),
+            &[0x89, b'P', b'N', b'G', 0xff]
+        );
+    }
+
+    #[test]
+    fn credential_headers_are_secret() {
+        for name in [
+            "Authorization",
+            "Cookie",
+            "X-Api-Key",
+            "x-auth-token",
+            "Set-Cookie",
+        ] {
+            assert!(Cassette::is_secret_header(name), "{}", name);
+        }
+        for name in ["content-type", "accept", "x-request-id"] {
+            assert!(!Cassette::is_secret_header(name), "{}", name);
+        }
+    }
+}
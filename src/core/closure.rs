@@ -38,3 +38,28 @@ where
         Box::pin(async move { result.await.into_call_tool_result() })
     }
 }
+
+impl<T0, T1, S, R> CallToolHandler<S, PhantomData<dyn Fn(T0, T1) -> R>>
+    for DynamicMCPClosure<(T0, T1), R>
+where
+    T0: FromToolCallContextPart<S>,
+    T1: FromToolCallContextPart<S>,
+    S: Send + Sync + 'static,
+    R: IntoCallToolResult + Send + 'static,
+{
+    fn call(
+        self,
+        mut context: ToolCallContext<'_, S>,
+    ) -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
+        let first = match T0::from_tool_call_context_part(&mut context) {
+            Ok(first) => first,
+            Err(err) => return Box::pin(async move { Err(err) }),
+        };
+        let second = match T1::from_tool_call_context_part(&mut context) {
+            Ok(second) => second,
+            Err(err) => return Box::pin(async move { Err(err) }),
+        };
+        let result = (self.closure)((first, second));
+        Box::pin(async move { result.await.into_call_tool_result() })
+    }
+}
@@ -0,0 +1,1213 @@
+use crate::core::cache::ResponseCache;
+use crate::core::config::{CommandMetadata, ErrorMode, ResultFormat, StreamOutput};
+use crate::core::output::{self, BoundedOutput};
+use crate::core::process::ProcessGroup;
+use crate::core::progress::OutputNotifier;
+use crate::core::sandbox::Sandbox;
+use crate::core::template::{Template, TemplateMode};
+use duration_string::DurationString;
+use rmcp::ErrorData;
+use rmcp::model::{CallToolResult, Content, ErrorCode};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// A command line with all templates rendered, ready to be spawned.
+#[derive(Debug, Clone)]
+pub struct RenderedCommand {
+    pub command: String,
+    pub args: Vec<String>,
+    pub stdin: Option<String>,
+    pub env: Vec<(String, String)>,
+}
+
+impl RenderedCommand {
+    pub fn cache_key(&self) -> String {
+        let args = self.args.join("\0");
+        let env = self
+            .env
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join("\0");
+        ResponseCache::key(&[
+            &self.command,
+            &args,
+            self.stdin.as_deref().unwrap_or(""),
+            &env,
+        ])
+    }
+}
+
+pub struct CommandTool {
+    template: Template,
+    args_count: usize,
+    stdin_template_exist: bool,
+    stdout_mime_type: Option<String>,
+    max_binary_bytes: u64,
+    max_output_bytes: Option<u64>,
+    timeout: Option<Duration>,
+    kill_grace_period: Duration,
+    shell: bool,
+    sandbox: Option<Sandbox>,
+    stream_output: StreamOutput,
+    result_format: ResultFormat,
+    success_exit_codes: Vec<i32>,
+    cwd: Option<PathBuf>,
+    env_names: Vec<String>,
+    env_clear: bool,
+    inherit_env: Option<Vec<String>>,
+    error_mode: ErrorMode,
+    cache: Option<ResponseCache>,
+}
+
+impl CommandTool {
+    const COMMAND_TEMPLATE_NAME: &'static str = "command";
+    const STDIN_TEMPLATE_NAME: &'static str = "stdin";
+    const KILLED_OUTPUT_GRACE: Duration = Duration::from_secs(1);
+    const DEFAULT_KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+    // Longer lines are streamed in pieces so a missing newline cannot buffer unbounded output
+    const MAX_STREAMED_LINE_BYTES: usize = 64 * 1024;
+    #[cfg(unix)]
+    const SHELL: (&'static str, &'static str) = ("sh", "-c");
+    #[cfg(windows)]
+    const SHELL: (&'static str, &'static str) = ("cmd", "/C");
+
+    fn command_args_template_name(idx: usize) -> String {
+        format!("args_{}", idx)
+    }
+
+    fn env_template_name(name: &str) -> String {
+        format!("env_{}", name)
+    }
+
+    pub fn new(
+        tool_index: usize,
+        command_metadata: CommandMetadata,
+        error_mode: ErrorMode,
+        max_output_bytes: Option<u64>,
+        cache: Option<ResponseCache>,
+        template_mode: TemplateMode,
+    ) -> Self {
+        let CommandMetadata {
+            command: command_template,
+            args: args_template,
+            stdin: stdin_template,
+            stdout_mime_type,
+            max_binary_bytes,
+            timeout,
+            kill_grace_period,
+            cwd,
+            env: env_template,
+            env_clear,
+            inherit_env,
+            result_format,
+            success_exit_codes,
+            shell,
+            sandbox,
+            stream_output,
+            ..
+        } = command_metadata;
+        let shell = shell.unwrap_or(false);
+
+        let kill_grace_period = kill_grace_period
+            .map(|grace_period| {
+                DurationString::from_string(grace_period.clone())
+                    .unwrap_or_else(|err| {
+                        panic!(
+                            "Invalid kill grace period {}, tool index {}: {}",
+                            grace_period, tool_index, err
+                        )
+                    })
+                    .into()
+            })
+            .unwrap_or(Self::DEFAULT_KILL_GRACE_PERIOD);
+
+        let timeout = timeout.map(|timeout| {
+            DurationString::from_string(timeout.clone())
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "Invalid command timeout {}, tool index {}: {}",
+                        timeout, tool_index, err
+                    )
+                })
+                .into()
+        });
+
+        // Initialize template once when the tool is created
+        let mut template = Template::new(template_mode);
+        let registered = if shell {
+            // Every input interpolated into a shell script is quoted unless a formatter is given
+            template.add_shell_template(Self::COMMAND_TEMPLATE_NAME, &command_template)
+        } else {
+            template.add_template(Self::COMMAND_TEMPLATE_NAME, &command_template)
+        };
+        registered.unwrap_or_else(|err| {
+            panic!(
+                "Error registering command template, tool index {}: {}: {}",
+                tool_index, command_template, err
+            )
+        });
+
+        let stdin_template_exist = if let Some(ref stdin_template) = stdin_template {
+            template
+                .add_template(Self::STDIN_TEMPLATE_NAME, stdin_template)
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "Error registering stdin template, tool index {}: {}",
+                        tool_index, err
+                    )
+                });
+            true
+        } else {
+            false
+        };
+
+        let args_template = args_template.unwrap_or_default();
+        for (i, args) in args_template.iter().enumerate() {
+            let template_name = Self::command_args_template_name(i);
+            template
+                .add_template(&template_name, args)
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "Error registering args template, tool index {}, arg index {}: {}",
+                        tool_index, i, err
+                    )
+                });
+        }
+
+        let env_template = env_template.unwrap_or_default();
+        let mut env_names: Vec<String> = env_template.keys().cloned().collect();
+        env_names.sort();
+        for name in env_names.iter() {
+            template
+                .add_template(&Self::env_template_name(name), &env_template[name])
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "Error registering env template, tool index {}, env name {}: {}",
+                        tool_index, name, err
+                    )
+                });
+        }
+
+        Self {
+            template,
+            args_count: args_template.len(),
+            stdin_template_exist,
+            stdout_mime_type,
+            max_binary_bytes: max_binary_bytes.unwrap_or(output::DEFAULT_MAX_BINARY_BYTES),
+            max_output_bytes,
+            timeout,
+            kill_grace_period,
+            shell,
+            sandbox: sandbox.map(|sandbox_config| Sandbox::new(tool_index, sandbox_config)),
+            stream_output: stream_output.unwrap_or_default(),
+            result_format: result_format.unwrap_or_default(),
+            success_exit_codes: success_exit_codes.unwrap_or_else(|| vec![0]),
+            cwd: cwd.map(PathBuf::from),
+            env_names,
+            env_clear: env_clear.unwrap_or(false),
+            inherit_env,
+            error_mode,
+            cache,
+        }
+    }
+
+    pub fn render(&self, context: &Value) -> Result<RenderedCommand, ErrorData> {
+        let command = self
+            .template
+            .render(Self::COMMAND_TEMPLATE_NAME, context)
+            .map_err(|err| {
+                ErrorData::new(
+                    ErrorCode::PARSE_ERROR,
+                    format!("Error while rendering command template: {}", err),
+                    None,
+                )
+            })?;
+
+        let args = (0..self.args_count)
+            .map(|i| {
+                self.template
+                    .render(&Self::command_args_template_name(i), context)
+                    .map_err(|err| {
+                        ErrorData::new(
+                            ErrorCode::PARSE_ERROR,
+                            format!("Error while rendering args template: {}", err),
+                            None,
+                        )
+                    })
+            })
+            .collect::<Result<Vec<String>, ErrorData>>()?;
+
+        let stdin = if self.stdin_template_exist {
+            let stdin_data = self
+                .template
+                .render(Self::STDIN_TEMPLATE_NAME, context)
+                .map_err(|err| {
+                    ErrorData::new(
+                        ErrorCode::PARSE_ERROR,
+                        format!("Error while rendering stdin template: {}", err),
+                        None,
+                    )
+                })?;
+            Some(stdin_data)
+        } else {
+            None
+        };
+
+        let env = self
+            .env_names
+            .iter()
+            .map(|name| {
+                self.template
+                    .render(&Self::env_template_name(name), context)
+                    .map(|value| (name.clone(), value))
+                    .map_err(|err| {
+                        ErrorData::new(
+                            ErrorCode::PARSE_ERROR,
+                            format!(
+                                "Error while rendering env template, env name {}: {}",
+                                name, err
+                            ),
+                            None,
+                        )
+                    })
+            })
+            .collect::<Result<Vec<(String, String)>, ErrorData>>()?;
+
+        Ok(RenderedCommand {
+            command,
+            args,
+            stdin,
+            env,
+        })
+    }
+
+    /// Returns the program and the arguments a rendered command is run with.
+    fn argv(&self, rendered: &RenderedCommand) -> (PathBuf, Vec<String>) {
+        if self.shell {
+            let (shell, script_flag) = Self::SHELL;
+            // The script is followed by $0, then the rendered args as positional parameters
+            let args = [script_flag, &rendered.command, shell]
+                .into_iter()
+                .map(str::to_string)
+                .chain(rendered.args.iter().cloned())
+                .collect();
+            (PathBuf::from(shell), args)
+        } else {
+            (self.program_path(&rendered.command), rendered.args.clone())
+        }
+    }
+
+    /// Describes how a rendered command would be run: its command line, working directory,
+    /// environment and stdin.
+    pub fn preview(&self, rendered: &RenderedCommand) -> String {
+        let (program, args) = self.argv(rendered);
+        let command_line = std::iter::once(program.to_string_lossy().to_string())
+            .chain(args)
+            .map(|arg| Self::quote_arg(&arg))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let mut lines = vec![format!("Command: {}", command_line)];
+        if let Some(ref cwd) = self.cwd {
+            lines.push(format!("Working directory: {}", cwd.display()));
+        }
+        if self.env_clear {
+            lines.push("Environment: cleared".to_string());
+        }
+        if let Some(ref inherit_env) = self.inherit_env {
+            lines.push(format!("Environment: inherits {}", inherit_env.join(", ")));
+        }
+        for (name, value) in rendered.env.iter() {
+            lines.push(format!("Environment: {}={}", name, Self::quote_arg(value)));
+        }
+        if self.sandbox.is_some() {
+            lines.push("Sandbox: enabled".to_string());
+        }
+        if let Some(ref stdin) = rendered.stdin {
+            lines.push(format!("Stdin:\n{}", stdin));
+        }
+        lines.join("\n")
+    }
+
+    /// Quotes an argument for display when the shell would split or expand it.
+    fn quote_arg(arg: &str) -> String {
+        let plain = !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+        if plain {
+            arg.to_string()
+        } else {
+            Template::shell_quote(arg)
+        }
+    }
+
+    /// Builds the result a rendered command would return if it printed `stdout` and `stderr`
+    /// and exited with `exit_code`, without running it.
+    pub fn mock_result(
+        &self,
+        rendered: &RenderedCommand,
+        exit_code: i32,
+        stdout: &str,
+        stderr: &str,
+    ) -> Result<CallToolResult, ErrorData> {
+        let output = |text: &str| BoundedOutput {
+            data: text.as_bytes().to_vec(),
+            truncated: false,
+        };
+        self.build_result(
+            rendered,
+            Some(exit_code),
+            output(stdout),
+            output(stderr),
+            Duration::ZERO,
+        )
+    }
+
+    pub fn stream_output(&self) -> &StreamOutput {
+        &self.stream_output
+    }
+
+    pub async fn call(
+        &self,
+        context: &Value,
+        ct: CancellationToken,
+        notifier: Option<OutputNotifier>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let rendered = self.render(context)?;
+
+        let Some(ref cache) = self.cache else {
+            return self.execute(rendered, ct, notifier).await;
+        };
+
+        let cache_key = rendered.cache_key();
+        if let Some(result) = cache.get(&cache_key).await {
+            return Ok(result);
+        }
+
+        let result = self.execute(rendered, ct, notifier).await?;
+        if let Some(ttl) = cache.ttl_for(None) {
+            cache.insert(&cache_key, &result, ttl).await;
+        }
+
+        Ok(result)
+    }
+
+    /// Returns warnings for command lines that interpolate raw inputs into inline scripts,
+    /// such as `bash -c "echo { input.x }"`, which allows shell injection.
+    pub fn validation_warnings(
+        tool_name: &str,
+        command_metadata: &CommandMetadata,
+        template_mode: TemplateMode,
+    ) -> Vec<String> {
+        if command_metadata.shell.unwrap_or(false) {
+            return vec![];
+        }
+
+        let template = Template::new(template_mode);
+
+        let args = command_metadata.args.clone().unwrap_or_default();
+        args.iter()
+            .zip(args.iter().skip(1))
+            .filter(|(flag, _)| Self::is_inline_script_flag(flag))
+            .flat_map(|(flag, script)| {
+                template
+                    .unescaped_shell_placeholders(script)
+                    .into_iter()
+                    .map(|placeholder| {
+                        format!(
+                            "tool {}: {} is interpolated into the `{}` script of {} without shell_escape, use `shell: true` or `{}`",
+                            tool_name,
+                            placeholder,
+                            flag,
+                            command_metadata.command,
+                            template.shell_escaped(&placeholder)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Matches `-c` as well as short combined flags such as `-ec` or `-lc`.
+    fn is_inline_script_flag(arg: &str) -> bool {
+        arg.strip_prefix('-').is_some_and(|flags| {
+            flags.len() <= 3
+                && flags.chars().all(|flag| flag.is_ascii_alphabetic())
+                && flags.ends_with('c')
+        })
+    }
+
+    /// Resolves a relative program path such as `./script.sh` against the configured `cwd`.
+    ///
+    /// Bare program names are left alone so they are still looked up in `PATH`.
+    fn program_path(&self, program: &str) -> PathBuf {
+        let path = Path::new(program);
+        match self.cwd {
+            Some(ref cwd) if path.is_relative() && path.components().count() > 1 => cwd.join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// Reads a pipe until EOF, keeping at most `limit` bytes.
+    ///
+    /// Past the limit, `overflow` is cancelled and reading stops if given, otherwise the
+    /// rest of the output is drained and discarded so the process never blocks on a full pipe.
+    /// Lines within the limit are forwarded to `notifier` as they are read.
+    fn spawn_reader<T>(
+        pipe: Option<T>,
+        limit: Option<u64>,
+        overflow: Option<CancellationToken>,
+        notifier: Option<(OutputNotifier, &'static str)>,
+    ) -> JoinHandle<BoundedOutput>
+    where
+        T: AsyncRead + Unpin + Send + 'static,
+    {
+        tokio::spawn(async move {
+            let mut output = BoundedOutput::default();
+            let Some(mut pipe) = pipe else {
+                return output;
+            };
+
+            let mut chunk = [0u8; 8192];
+            let mut pending_line = Vec::new();
+            loop {
+                let read = match pipe.read(&mut chunk).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => read,
+                };
+
+                if let Some((ref notifier, stream)) = notifier
+                    && !output.truncated
+                {
+                    pending_line.extend_from_slice(&chunk[..read]);
+                    while let Some(end) = pending_line
+                        .iter()
+                        .position(|byte| *byte == b'\n')
+                        .or_else(|| {
+                            (pending_line.len() >= Self::MAX_STREAMED_LINE_BYTES)
+                                .then_some(Self::MAX_STREAMED_LINE_BYTES - 1)
+                        })
+                    {
+                        let line: Vec<u8> = pending_line.drain(..=end).collect();
+                        let line = String::from_utf8_lossy(&line);
+                        notifier.notify_line(stream, line.trim_end()).await;
+                    }
+                }
+
+                if output.push(&chunk[..read], limit)
+                    && let Some(ref overflow) = overflow
+                {
+                    overflow.cancel();
+                    break;
+                }
+            }
+
+            if let Some((ref notifier, stream)) = notifier
+                && !pending_line.is_empty()
+            {
+                let line = String::from_utf8_lossy(&pending_line);
+                notifier.notify_line(stream, line.trim_end()).await;
+            }
+            output
+        })
+    }
+
+    /// Collects what a reader read before its process group was killed.
+    async fn collect_output(reader: JoinHandle<BoundedOutput>) -> BoundedOutput {
+        // A killed process group closes its pipes right away, unless a child escaped the group
+        tokio::time::timeout(Self::KILLED_OUTPUT_GRACE, reader)
+            .await
+            .ok()
+            .and_then(|output| output.ok())
+            .unwrap_or_default()
+    }
+
+    /// Converts the captured output of a finished command into a tool result.
+    ///
+    /// `exit_code` is `None` when the process was killed by a signal, including when
+    /// its stdout grew past the output limit.
+    fn build_result(
+        &self,
+        rendered: &RenderedCommand,
+        exit_code: Option<i32>,
+        stdout: BoundedOutput,
+        stderr: BoundedOutput,
+        duration: Duration,
+    ) -> Result<CallToolResult, ErrorData> {
+        let error_mode = &self.error_mode;
+        let output_limit = self.max_output_bytes.unwrap_or_default();
+        let binary_mime_type = self
+            .stdout_mime_type
+            .as_ref()
+            .filter(|mime_type| output::is_binary_mime_type(mime_type));
+
+        if stdout.truncated && binary_mime_type.is_some() {
+            return output::execution_error(
+                error_mode,
+                format!(
+                    "Binary output of {} exceeds the limit of {} bytes",
+                    rendered.command, self.max_binary_bytes
+                ),
+            );
+        }
+
+        // A command killed at the output limit did what it was asked, its output is only cut
+        let killed_at_output_limit = exit_code.is_none() && stdout.truncated;
+        let succeeded = killed_at_output_limit
+            || exit_code.is_some_and(|exit_code| self.success_exit_codes.contains(&exit_code));
+        let stderr = stderr.to_text(output_limit, None);
+
+        let media_content = binary_mime_type.map(|mime_type| {
+            let uri = format!("command:{}", rendered.command);
+            output::binary_content(&stdout.data, mime_type, &uri)
+        });
+
+        let stdout_value = if media_content.is_some() {
+            Value::Null
+        } else {
+            let stdout_text = stdout.to_text(output_limit, None);
+            match serde_json::from_str::<Value>(&stdout_text) {
+                Ok(json_output) if !stdout.truncated => json_output,
+                _ => Value::String(stdout_text),
+            }
+        };
+
+        if self.result_format == ResultFormat::STRUCTURED {
+            let structured = json!({
+                "exit_code": exit_code,
+                "stdout": stdout_value,
+                "stderr": stderr,
+                "truncated": stdout.truncated,
+                "duration_ms": duration.as_millis() as u64,
+            });
+
+            if !succeeded {
+                return output::execution_error(error_mode, structured.to_string());
+            }
+
+            let mut contents = vec![output::json_content(structured)?];
+            contents.extend(media_content);
+            return Ok(CallToolResult::success(contents));
+        }
+
+        if !succeeded {
+            return output::execution_error(
+                error_mode,
+                format!("Error while executing a command: {}", stderr),
+            );
+        }
+
+        let content = match (media_content, stdout_value) {
+            (Some(media_content), _) => media_content,
+            (None, Value::String(stdout_text)) => Content::text(stdout_text),
+            (None, json_output) => output::json_content(json_output)?,
+        };
+        Ok(CallToolResult::success(vec![content]))
+    }
+
+    /// Spawns a rendered command and converts its output into a tool result.
+    ///
+    /// The command's process group is terminated when the configured timeout elapses or `ct`
+    /// is cancelled, and killed once the grace period is over. It is killed right away when
+    /// stdout grows past the output limit. The command runs until its stdout and stderr are
+    /// closed, including by background processes it started.
+    pub async fn execute(
+        &self,
+        rendered: RenderedCommand,
+        ct: CancellationToken,
+        notifier: Option<OutputNotifier>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let error_mode = &self.error_mode;
+
+        let (program, args) = self.argv(&rendered);
+        let mut command = tokio::process::Command::new(program);
+
+        if let Some(ref cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+
+        if self.env_clear || self.inherit_env.is_some() {
+            command.env_clear();
+        }
+        for name in self.inherit_env.iter().flatten() {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+        command.envs(rendered.env.iter().cloned());
+
+        if rendered.stdin.is_some() {
+            command.stdin(Stdio::piped());
+        }
+
+        ProcessGroup::configure(&mut command);
+
+        if let Some(ref sandbox) = self.sandbox {
+            sandbox.configure(&mut command, self.cwd.as_deref());
+        }
+
+        let started_at = Instant::now();
+
+        let spawn_result = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(&args)
+            .spawn();
+
+        let mut child = match spawn_result {
+            Ok(child) => child,
+            Err(err) => {
+                return output::execution_error(
+                    error_mode,
+                    format!("Error while spawning a process: {}", err),
+                );
+            }
+        };
+
+        let mut process_group = ProcessGroup::new(child.id());
+
+        if let Some(stdin_data) = rendered.stdin.clone() {
+            let mut stdin = match child.stdin.take() {
+                Some(stdin) => stdin,
+                None => {
+                    return Err(ErrorData::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        "Error while spawning a process: stdin is None".to_string(),
+                        None,
+                    ));
+                }
+            };
+
+            // Written in the background so a child that never reads stdin cannot block the timeout
+            tokio::spawn(async move {
+                if let Err(err) = stdin.write_all(stdin_data.as_bytes()).await {
+                    tracing::warn!(error = %err, "Error while writing stdin");
+                }
+            });
+        }
+
+        // Binary stdout cannot be truncated meaningfully, so it is capped by max_binary_bytes instead
+        let binary_stdout = self
+            .stdout_mime_type
+            .as_ref()
+            .is_some_and(|mime_type| output::is_binary_mime_type(mime_type));
+        let stdout_limit = if binary_stdout {
+            Some(self.max_binary_bytes)
+        } else {
+            self.max_output_bytes
+        };
+
+        // Binary stdout is not line based, so only its stderr is streamed
+        let stdout_notifier = notifier
+            .clone()
+            .filter(|_| !binary_stdout)
+            .map(|notifier| (notifier, OutputNotifier::STDOUT));
+        let stderr_notifier = notifier.map(|notifier| (notifier, OutputNotifier::STDERR));
+
+        let output_limit = CancellationToken::new();
+        let mut stdout_reader = Self::spawn_reader(
+            child.stdout.take(),
+            stdout_limit,
+            Some(output_limit.clone()),
+            stdout_notifier,
+        );
+        let mut stderr_reader = Self::spawn_reader(
+            child.stderr.take(),
+            self.max_output_bytes,
+            None,
+            stderr_notifier,
+        );
+
+        let timeout = self.timeout;
+        let deadline = async move {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        // The command is done once its pipes are closed too, as background processes it started
+        // may still hold them after the leader exited, and those stay under the timeout
+        let mut stdout_output = None;
+        let mut stderr_output = None;
+        let finished = async {
+            let status = child.wait().await;
+            stdout_output = Some((&mut stdout_reader).await.unwrap_or_default());
+            stderr_output = Some((&mut stderr_reader).await.unwrap_or_default());
+            status
+        };
+
+        let outcome = tokio::select! {
+            status = finished => Some(status),
+            _ = deadline => None,
+            _ = ct.cancelled() => None,
+            _ = output_limit.cancelled() => None,
+        };
+
+        let status = match outcome {
+            Some(status) => {
+                process_group.disarm();
+                status.map_err(|err| {
+                    ErrorData::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        format!("Error while waiting for a process: {}", err),
+                        None,
+                    )
+                })?
+            }
+            None => {
+                // Give the command a chance to clean up, unless it is flooding its output
+                if !output_limit.is_cancelled() {
+                    process_group.terminate();
+                    let _ = tokio::time::timeout(self.kill_grace_period, child.wait()).await;
+                }
+                // Also kills processes of the group that outlived the leader
+                process_group.kill();
+                // Reap the killed child so it does not linger as a zombie
+                let _ = child.wait().await;
+
+                let partial_stdout = match stdout_output {
+                    Some(output) => output,
+                    None => Self::collect_output(stdout_reader).await,
+                };
+                let partial_stderr = match stderr_output {
+                    Some(output) => output,
+                    None => Self::collect_output(stderr_reader).await,
+                };
+
+                if ct.is_cancelled() {
+                    return Err(ErrorData::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        format!("Command {} was cancelled", rendered.command),
+                        None,
+                    ));
+                }
+
+                if partial_stdout.truncated {
+                    return self.build_result(
+                        &rendered,
+                        None,
+                        partial_stdout,
+                        partial_stderr,
+                        started_at.elapsed(),
+                    );
+                }
+
+                return output::execution_error(
+                    error_mode,
+                    format!(
+                        "Command {} timed out after {:?} and was killed. Partial stdout: {}. Partial stderr: {}",
+                        rendered.command,
+                        self.timeout.unwrap_or_default(),
+                        String::from_utf8_lossy(&partial_stdout.data),
+                        String::from_utf8_lossy(&partial_stderr.data),
+                    ),
+                );
+            }
+        };
+
+        self.build_result(
+            &rendered,
+            status.code(),
+            stdout_output.unwrap_or_default(),
+            stderr_output.unwrap_or_default(),
+            started_at.elapsed(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::prelude::BASE64_STANDARD;
+    use serde_json::json;
+
+    fn command_tool(
+        command: &str,
+        args: &[&str],
+        extra: Value,
+        error_mode: ErrorMode,
+        max_output_bytes: Option<u64>,
+    ) -> CommandTool {
+        let mut command_metadata = json!({"command": command, "args": args, "input_schema": {}});
+        if let Value::Object(extra) = extra {
+            command_metadata.as_object_mut().unwrap().extend(extra);
+        }
+        CommandTool::new(
+            0,
+            serde_json::from_value(command_metadata).unwrap(),
+            error_mode,
+            max_output_bytes,
+            None,
+            TemplateMode::SIMPLE,
+        )
+    }
+
+    async fn call_command(
+        command: &str,
+        args: &[&str],
+        extra: Value,
+        error_mode: ErrorMode,
+    ) -> Result<CallToolResult, ErrorData> {
+        let tool = command_tool(command, args, extra, error_mode, None);
+        tool.call(&json!({"input": {}}), CancellationToken::new(), None)
+            .await
+    }
+
+    /// Whether a process has exited, counting unreaped zombies as exited.
+    fn has_exited(pid: &str) -> bool {
+        match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => stat
+                .rsplit(')')
+                .next()
+                .is_some_and(|rest| rest.trim_start().starts_with('Z')),
+            Err(_) => true,
+        }
+    }
+
+    fn text(result: &CallToolResult) -> String {
+        let content = &result.content.as_ref().unwrap()[0];
+        content.as_text().unwrap().text.clone()
+    }
+
+    fn json_text(result: &CallToolResult) -> Value {
+        serde_json::from_str(&text(result)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn result_error_mode_returns_command_failures_as_error_results() {
+        let result = call_command(
+            "sh",
+            &["-c", "echo oops >&2; exit 2"],
+            json!({}),
+            ErrorMode::RESULT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(text(&result).contains("oops"), "{}", text(&result));
+
+        let result = call_command("/nonexistent/binary", &[], json!({}), ErrorMode::RESULT)
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(
+            text(&result).starts_with("Error while spawning a process"),
+            "{}",
+            text(&result)
+        );
+    }
+
+    #[tokio::test]
+    async fn stdout_mime_type_returns_command_stdout_as_media_content() {
+        let result = call_command(
+            "printf",
+            &["png-bytes"],
+            json!({"stdout_mime_type": "image/png"}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        let image = result.content.as_ref().unwrap()[0].as_image().unwrap();
+        assert_eq!(BASE64_STANDARD.decode(&image.data).unwrap(), b"png-bytes");
+
+        let result = call_command(
+            "printf",
+            &["plain"],
+            json!({"stdout_mime_type": "text/plain"}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(text(&result), "plain");
+    }
+
+    #[tokio::test]
+    async fn protocol_error_mode_keeps_command_failures_as_protocol_errors() {
+        assert!(
+            call_command("/nonexistent/binary", &[], json!({}), ErrorMode::PROTOCOL)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_stdout_over_max_binary_bytes_is_rejected() {
+        let result = call_command(
+            "printf",
+            &["png-bytes"],
+            json!({"stdout_mime_type": "image/png", "max_binary_bytes": 4}),
+            ErrorMode::RESULT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.is_error, Some(true));
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_the_whole_process_group() {
+        let started = std::time::Instant::now();
+        let result = call_command(
+            "sh",
+            &["-c", "sleep 30 & echo $!; wait"],
+            json!({"timeout": "200ms"}),
+            ErrorMode::RESULT,
+        )
+        .await
+        .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(result.is_error, Some(true));
+
+        let message = text(&result);
+        assert!(message.contains("timed out"), "{}", message);
+        let pid = message
+            .split("Partial stdout: ")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap();
+        // The background sleep is killed with the group instead of outliving the shell
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(has_exited(pid), "process {} still running", pid);
+    }
+
+    #[tokio::test]
+    async fn cancellation_kills_the_command() {
+        let tool = command_tool("sleep", &["30"], json!({}), ErrorMode::RESULT, None);
+        let ct = CancellationToken::new();
+        let cancel = ct.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+
+        let err = tool
+            .call(&json!({"input": {}}), ct, None)
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("was cancelled"), "{}", err.message);
+    }
+
+    #[tokio::test]
+    async fn env_templates_are_rendered_into_the_command_environment() {
+        let tool = command_tool(
+            "sh",
+            &["-c", "printf %s \"$REGION\""],
+            json!({"env": {"REGION": "{ input.region }"}}),
+            ErrorMode::PROTOCOL,
+            None,
+        );
+        let result = tool
+            .call(
+                &json!({"input": {"region": "eu-west-1"}}),
+                CancellationToken::new(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "eu-west-1");
+    }
+
+    #[tokio::test]
+    async fn inherit_env_passes_only_listed_server_variables() {
+        let result = call_command(
+            "/usr/bin/env",
+            &[],
+            json!({"inherit_env": ["PATH"], "env": {"EXTRA": "1"}}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        let mut names: Vec<String> = text(&result)
+            .lines()
+            .filter_map(|line| line.split('=').next())
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["EXTRA", "PATH"]);
+
+        let result = call_command(
+            "/usr/bin/env",
+            &[],
+            json!({"env_clear": true}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(text(&result), "");
+    }
+
+    #[tokio::test]
+    async fn relative_programs_are_resolved_against_cwd() {
+        let result = call_command(
+            "./sh",
+            &["-c", "pwd"],
+            json!({"cwd": "/bin"}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        assert!(
+            text(&result).trim_end().ends_with("bin"),
+            "{}",
+            text(&result)
+        );
+    }
+
+    #[tokio::test]
+    async fn max_output_bytes_kills_the_command_and_truncates_stdout() {
+        let tool = command_tool("yes", &[], json!({}), ErrorMode::PROTOCOL, Some(8));
+        let result = tool
+            .call(&json!({"input": {}}), CancellationToken::new(), None)
+            .await
+            .unwrap();
+        assert_ne!(result.is_error, Some(true));
+        assert_eq!(
+            text(&result),
+            "y\ny\ny\ny\n\n[Output truncated to 8 bytes, original size more than 8 bytes]"
+        );
+
+        let tool = command_tool(
+            "yes",
+            &[],
+            json!({"result_format": "STRUCTURED"}),
+            ErrorMode::PROTOCOL,
+            Some(8),
+        );
+        let result = tool
+            .call(&json!({"input": {}}), CancellationToken::new(), None)
+            .await
+            .unwrap();
+        assert_ne!(result.is_error, Some(true));
+        let structured = json_text(&result);
+        assert_eq!(structured["exit_code"], Value::Null);
+        assert_eq!(structured["truncated"], json!(true));
+        assert!(
+            structured["stdout"]
+                .as_str()
+                .unwrap()
+                .starts_with("y\ny\ny\ny\n\n[Output truncated")
+        );
+    }
+
+    #[tokio::test]
+    async fn structured_results_report_exit_code_stdout_and_stderr() {
+        let result = call_command(
+            "sh",
+            &["-c", "echo '{\"a\": 1}'; echo warning >&2"],
+            json!({"result_format": "STRUCTURED"}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        let structured = json_text(&result);
+        assert_eq!(structured["exit_code"], json!(0));
+        assert_eq!(structured["stdout"], json!({"a": 1}));
+        assert_eq!(structured["stderr"], json!("warning\n"));
+        assert!(structured["duration_ms"].is_u64());
+
+        let result = call_command(
+            "sh",
+            &["-c", "echo failed >&2; exit 3"],
+            json!({"result_format": "STRUCTURED"}),
+            ErrorMode::RESULT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let structured = json_text(&result);
+        assert_eq!(structured["exit_code"], json!(3));
+        assert_eq!(structured["stderr"], json!("failed\n"));
+    }
+
+    #[tokio::test]
+    async fn success_exit_codes_accept_listed_non_zero_exit_codes() {
+        let extra = json!({"success_exit_codes": [0, 1]});
+        let result = call_command("sh", &["-c", "exit 1"], extra.clone(), ErrorMode::RESULT)
+            .await
+            .unwrap();
+        assert_ne!(result.is_error, Some(true));
+
+        let result = call_command("sh", &["-c", "exit 2"], extra, ErrorMode::RESULT)
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+    }
+
+    #[tokio::test]
+    async fn shell_mode_quotes_interpolated_inputs() {
+        let tool = command_tool(
+            "printf %s { input.text }",
+            &[],
+            json!({"shell": true}),
+            ErrorMode::PROTOCOL,
+            None,
+        );
+        let result = tool
+            .call(
+                &json!({"input": {"text": "a; echo injected"}}),
+                CancellationToken::new(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "a; echo injected");
+    }
+
+    #[test]
+    fn inline_scripts_with_unescaped_inputs_are_reported() {
+        let command_metadata = serde_json::from_value(json!({
+            "command": "bash",
+            "args": ["-ec", "echo { input.a } { input.b | shell_escape }"],
+            "input_schema": {},
+        }))
+        .unwrap();
+        let warnings =
+            CommandTool::validation_warnings("tool", &command_metadata, TemplateMode::SIMPLE);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("input.a"), "{}", warnings[0]);
+
+        let command_metadata = serde_json::from_value(json!({
+            "command": "echo { input.a }",
+            "shell": true,
+            "input_schema": {},
+        }))
+        .unwrap();
+        assert!(
+            CommandTool::validation_warnings("tool", &command_metadata, TemplateMode::SIMPLE)
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout_terminates_before_killing() {
+        let result = call_command(
+            "sh",
+            &["-c", "trap 'echo cleaned up; exit 0' TERM; sleep 30 & wait"],
+            json!({"timeout": "200ms"}),
+            ErrorMode::RESULT,
+        )
+        .await
+        .unwrap();
+        assert!(text(&result).contains("cleaned up"), "{}", text(&result));
+
+        let started = std::time::Instant::now();
+        let result = call_command(
+            "sh",
+            &["-c", "trap '' TERM; while true; do sleep 1; done"],
+            json!({"timeout": "100ms", "kill_grace_period": "200ms"}),
+            ErrorMode::RESULT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,12 +1,19 @@
+use crate::core::openapi;
+pub use crate::core::template::TemplateMode;
 use rmcp::model::{Implementation, JsonObject, ServerCapabilities, ToolAnnotations};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::path::Path;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(serde::Deserialize, Debug, Clone)]
 pub enum ToolType {
     HTTP,
     COMMAND,
+    STATIC,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(serde::Deserialize, Debug, Clone)]
 pub enum HttpMethod {
     GET,
@@ -15,6 +22,15 @@ pub enum HttpMethod {
     DELETE,
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub enum ErrorMode {
+    #[default]
+    PROTOCOL,
+    RESULT,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(serde::Deserialize, Debug, Clone)]
 pub enum TransportType {
     STDIO,
@@ -29,6 +45,48 @@ pub struct HttpMetadata {
     pub headers: Option<HashMap<String, String>>,
     pub input_schema: JsonObject,
     pub output_schema: Option<JsonObject>,
+    pub response_config: Option<HttpResponseConfig>,
+    pub max_binary_bytes: Option<u64>,
+    pub pagination: Option<PaginationConfig>,
+}
+
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+#[derive(serde::Deserialize, Debug, Clone)]
+pub enum PaginationStrategy {
+    LINK_HEADER,
+    CURSOR,
+    PAGE,
+    OFFSET,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct PaginationConfig {
+    pub strategy: PaginationStrategy,
+    pub items_path: Option<String>,
+    pub cursor_path: Option<String>,
+    pub cursor_param: Option<String>,
+    pub page_param: Option<String>,
+    pub start_page: Option<u64>,
+    pub offset_param: Option<String>,
+    pub max_pages: Option<u32>,
+    pub max_items: Option<usize>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct HttpResponseConfig {
+    pub envelope: Option<bool>,
+    pub header_allowlist: Option<Vec<String>>,
+    pub success_status_codes: Option<Vec<u16>>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum ResultFormat {
+    #[default]
+    #[serde(alias = "stdout")]
+    STDOUT,
+    #[serde(alias = "structured")]
+    STRUCTURED,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -38,6 +96,66 @@ pub struct CommandMetadata {
     pub stdin: Option<String>,
     pub input_schema: JsonObject,
     pub output_schema: Option<JsonObject>,
+    pub stdout_mime_type: Option<String>,
+    pub max_binary_bytes: Option<u64>,
+    pub timeout: Option<String>,
+    pub kill_grace_period: Option<String>,
+    pub cwd: Option<String>,
+    pub env: Option<HashMap<String, String>>,
+    pub env_clear: Option<bool>,
+    pub inherit_env: Option<Vec<String>>,
+    pub result_format: Option<ResultFormat>,
+    pub success_exit_codes: Option<Vec<i32>>,
+    pub shell: Option<bool>,
+    pub sandbox: Option<SandboxConfig>,
+    pub stream_output: Option<StreamOutput>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum StreamOutput {
+    #[default]
+    NONE,
+    PROGRESS,
+    LOG,
+    ALL,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct SandboxConfig {
+    pub network: Option<bool>,
+    pub read_only_paths: Option<Vec<String>>,
+    pub tmpfs_paths: Option<Vec<String>>,
+    pub seccomp: Option<bool>,
+    pub no_new_privileges: Option<bool>,
+    pub limits: Option<ResourceLimits>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct ResourceLimits {
+    pub cpu_seconds: Option<u64>,
+    pub memory_bytes: Option<u64>,
+    pub file_size_bytes: Option<u64>,
+    pub max_processes: Option<u64>,
+}
+
+/// A stand-in tool answering with canned responses, picked by the first rule matching the input.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct StaticMetadata {
+    pub input_schema: JsonObject,
+    pub output_schema: Option<JsonObject>,
+    pub rules: Option<Vec<StaticRule>>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub latency: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct StaticRule {
+    pub when: Option<JsonObject>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub latency: Option<String>,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -47,7 +165,112 @@ pub struct ToolData {
     pub tool_type: ToolType,
     pub http_metadata: Option<HttpMetadata>,
     pub command_metadata: Option<CommandMetadata>,
+    pub static_metadata: Option<StaticMetadata>,
     pub tool_annotations: Option<ToolAnnotations>,
+    pub error_mode: Option<ErrorMode>,
+    pub cache: Option<CacheConfig>,
+    pub max_output_bytes: Option<u64>,
+    pub mode: Option<ToolMode>,
+    pub background: Option<BackgroundConfig>,
+    pub max_concurrency: Option<usize>,
+    pub queue_timeout: Option<String>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub template_mode: Option<TemplateMode>,
+    pub tests: Option<Vec<ToolTest>>,
+}
+
+impl ToolData {
+    pub fn limits(&self) -> LimitsConfig {
+        LimitsConfig {
+            max_concurrency: self.max_concurrency,
+            queue_timeout: self.queue_timeout.clone(),
+            rate_limit: self.rate_limit.clone(),
+        }
+    }
+}
+
+/// A test case run by `easymcp test`.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ToolTest {
+    pub name: String,
+    pub input: Option<JsonObject>,
+    pub mock_response: Option<MockResponse>,
+    pub mock_responses: Option<Vec<MockResponse>>,
+    pub mock_output: Option<MockOutput>,
+    pub assertions: Vec<Assertion>,
+}
+
+/// Response of the mock HTTP server, 200 with an empty body by default.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct MockResponse {
+    pub status: Option<u16>,
+    pub headers: Option<HashMap<String, String>>,
+    pub body: Option<String>,
+}
+
+/// Output a command is assumed to produce, empty with exit code 0 by default.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct MockOutput {
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub exit_code: Option<i32>,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum AssertionTarget {
+    #[serde(alias = "request")]
+    REQUEST,
+    #[serde(alias = "result")]
+    RESULT,
+    #[serde(alias = "text")]
+    TEXT,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Assertion {
+    pub target: AssertionTarget,
+    pub path: Option<String>,
+    pub equals: Option<Value>,
+    pub matches: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct RateLimitConfig {
+    pub requests: u32,
+    pub interval: String,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct LimitsConfig {
+    pub max_concurrency: Option<usize>,
+    pub queue_timeout: Option<String>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum ToolMode {
+    #[default]
+    #[serde(alias = "sync")]
+    SYNC,
+    #[serde(alias = "background")]
+    BACKGROUND,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct BackgroundConfig {
+    pub retention: Option<String>,
+    pub max_jobs: Option<usize>,
+    pub disk_path: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CacheConfig {
+    pub ttl: String,
+    pub max_entries: Option<usize>,
+    pub respect_cache_control: Option<bool>,
+    pub disk_path: Option<String>,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -56,6 +279,8 @@ pub struct SseConfig {
     pub sse_path: Option<String>,
     pub post_path: Option<String>,
     pub keep_alive_duration: Option<String>,
+    pub limits: Option<LimitsConfig>,
+    pub session_limits: Option<LimitsConfig>,
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -73,23 +298,261 @@ impl Default for TransportConfig {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CassetteMode {
+    RECORD,
+    REPLAY,
+}
+
+/// Where upstream HTTP exchanges are recorded to or replayed from, set from the command line.
+#[derive(Debug, Clone)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    pub dir: String,
+}
+
+/// An OpenAPI 3 specification whose operations are imported as HTTP tools.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct OpenApiSource {
+    pub path: String,
+    pub base_url: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+    pub include_tags: Option<Vec<String>>,
+    pub exclude_tags: Option<Vec<String>>,
+    pub include_paths: Option<Vec<String>>,
+    pub exclude_paths: Option<Vec<String>>,
+}
+
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct DynamicMCPConfig {
+    #[serde(default)]
     pub tools: Vec<ToolData>,
+    pub openapi: Option<Vec<OpenApiSource>>,
     pub instruction: Option<String>,
     pub server_info: Option<Implementation>,
     pub server_capabilities: Option<ServerCapabilities>,
     pub transport_config: Option<TransportConfig>,
+    pub error_mode: Option<ErrorMode>,
+    pub max_output_bytes: Option<u64>,
+    pub variables: Option<HashMap<String, Value>>,
+    pub env_allowlist: Option<Vec<String>>,
+    pub secrets: Option<HashMap<String, String>>,
+    #[serde(skip)]
+    pub cassette: Option<CassetteConfig>,
 }
 
 impl DynamicMCPConfig {
     pub async fn new_from_file(file_path: String) -> Self {
-        let file_bytes = tokio::fs::read(file_path)
+        let file_bytes = tokio::fs::read(&file_path)
             .await
             .unwrap_or_else(|err| panic!("Error while reading the config file: {}", err));
-        match serde_yaml::from_str(String::from_utf8(file_bytes).unwrap().as_str()) {
-            Ok(data) => data,
-            Err(err) => panic!("Error while parsing the config file: {}", err),
+        let mut config: Self =
+            match serde_yaml::from_str(String::from_utf8(file_bytes).unwrap().as_str()) {
+                Ok(data) => data,
+                Err(err) => panic!("Error while parsing the config file: {}", err),
+            };
+
+        let config_dir = Path::new(&file_path)
+            .parent()
+            .and_then(|config_dir| std::path::absolute(config_dir).ok())
+            .unwrap_or_default();
+        config.resolve_relative_paths(&config_dir);
+        config.import_openapi().await;
+        config
+    }
+
+    /// Appends a tool per operation of the `openapi` specifications.
+    ///
+    /// Tools defined in `tools` take precedence over imported tools with the same name.
+    async fn import_openapi(&mut self) {
+        for source in self.openapi.iter().flatten() {
+            let (tools, warnings) = openapi::import(source)
+                .await
+                .unwrap_or_else(|err| panic!("Error while importing {}: {}", source.path, err));
+            for warning in warnings {
+                tracing::warn!(spec = %source.path, "{}", warning);
+            }
+
+            for tool in tools {
+                let tool: ToolData = serde_yaml::from_value(tool)
+                    .unwrap_or_else(|err| panic!("Error while importing {}: {}", source.path, err));
+                if !self.tools.iter().any(|existing| existing.name == tool.name) {
+                    self.tools.push(tool);
+                }
+            }
+        }
+    }
+
+    /// Resolves relative command working directories, sandbox paths, secret files and OpenAPI specifications against the config file's directory,
+    /// so tools behave the same wherever the server is launched from.
+    fn resolve_relative_paths(&mut self, config_dir: &Path) {
+        for path in self
+            .secrets
+            .iter_mut()
+            .flat_map(|secrets| secrets.values_mut())
+            .chain(
+                self.openapi
+                    .iter_mut()
+                    .flatten()
+                    .map(|source| &mut source.path),
+            )
+            .filter(|path| Path::new(path.as_str()).is_relative())
+        {
+            *path = config_dir.join(&path).to_string_lossy().to_string();
+        }
+
+        for path in self
+            .tools
+            .iter_mut()
+            .flat_map(|tool| {
+                let cache_path = tool
+                    .cache
+                    .as_mut()
+                    .and_then(|cache| cache.disk_path.as_mut());
+                let jobs_path = tool
+                    .background
+                    .as_mut()
+                    .and_then(|background| background.disk_path.as_mut());
+                cache_path.into_iter().chain(jobs_path)
+            })
+            .filter(|path| Path::new(path.as_str()).is_relative())
+        {
+            *path = config_dir.join(&path).to_string_lossy().to_string();
+        }
+
+        for command_metadata in self
+            .tools
+            .iter_mut()
+            .filter_map(|tool| tool.command_metadata.as_mut())
+        {
+            if let Some(ref cwd) = command_metadata.cwd
+                && Path::new(cwd).is_relative()
+            {
+                command_metadata.cwd = Some(config_dir.join(cwd).to_string_lossy().to_string());
+            }
+
+            if let Some(ref mut sandbox) = command_metadata.sandbox {
+                for path in sandbox
+                    .read_only_paths
+                    .iter_mut()
+                    .chain(sandbox.tmpfs_paths.iter_mut())
+                    .flatten()
+                    .filter(|path| Path::new(path.as_str()).is_relative())
+                {
+                    *path = config_dir.join(&path).to_string_lossy().to_string();
+                }
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_are_resolved_against_the_config_directory() {
+        let mut config: DynamicMCPConfig = serde_yaml::from_str(
+            r#"
+tools:
+  - name: relative
+    description: relative cwd
+    tool_type: COMMAND
+    command_metadata:
+      command: ls
+      cwd: scripts
+      input_schema: {}
+  - name: absolute
+    description: absolute cwd
+    tool_type: COMMAND
+    command_metadata:
+      command: ls
+      cwd: /tmp
+      input_schema: {}
+      sandbox:
+        read_only_paths: [data, /etc]
+        tmpfs_paths: [scratch]
+"#,
+        )
+        .unwrap();
+        config.resolve_relative_paths(Path::new("/etc/easymcp"));
+
+        let cwd = |index: usize| {
+            config.tools[index]
+                .command_metadata
+                .as_ref()
+                .unwrap()
+                .cwd
+                .clone()
+        };
+        assert_eq!(cwd(0).as_deref(), Some("/etc/easymcp/scripts"));
+        assert_eq!(cwd(1).as_deref(), Some("/tmp"));
+
+        let sandbox = config.tools[1]
+            .command_metadata
+            .as_ref()
+            .unwrap()
+            .sandbox
+            .clone()
+            .unwrap();
+        assert_eq!(
+            sandbox.read_only_paths.unwrap(),
+            vec!["/etc/easymcp/data", "/etc"]
+        );
+        assert_eq!(sandbox.tmpfs_paths.unwrap(), vec!["/etc/easymcp/scratch"]);
+    }
+
+    #[test]
+    fn relative_cache_and_job_directories_are_resolved_against_the_config_directory() {
+        let mut config: DynamicMCPConfig = serde_yaml::from_str(
+            r#"
+tools:
+  - name: relative
+    description: relative cache
+    tool_type: HTTP
+    http_metadata:
+      url: https://example.com
+      method: GET
+      input_schema: {}
+    cache:
+      ttl: 1m
+      disk_path: cache
+    mode: BACKGROUND
+    background:
+      disk_path: jobs
+  - name: absolute
+    description: absolute cache
+    tool_type: HTTP
+    http_metadata:
+      url: https://example.com
+      method: GET
+      input_schema: {}
+    cache:
+      ttl: 1m
+      disk_path: /var/cache/easymcp
+"#,
+        )
+        .unwrap();
+        config.resolve_relative_paths(Path::new("/etc/easymcp"));
+
+        let disk_path = |index: usize| {
+            config.tools[index]
+                .cache
+                .as_ref()
+                .unwrap()
+                .disk_path
+                .clone()
+        };
+        assert_eq!(disk_path(0).as_deref(), Some("/etc/easymcp/cache"));
+        assert_eq!(disk_path(1).as_deref(), Some("/var/cache/easymcp"));
+
+        let jobs_path = config.tools[0]
+            .background
+            .as_ref()
+            .unwrap()
+            .disk_path
+            .clone();
+        assert_eq!(jobs_path.as_deref(), Some("/etc/easymcp/jobs"));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::core::config::DynamicMCPConfig;
+use crate::core::template::Template;
+use chrono::{SecondsFormat, Utc};
+use rmcp::model::{CallToolResult, Extensions, RawContent};
+use rmcp::service::RequestContext;
+use rmcp::{ErrorData, RoleServer};
+use serde_json::{Map, Value, json};
+
+/// Id of the client session a tool call belongs to, added to the request extensions.
+#[derive(Clone)]
+pub struct SessionId(pub String);
+
+/// Authenticated user of a tool call, added to the request extensions by a transport that
+/// authenticates clients, or to the HTTP request extensions by an authentication layer in front
+/// of an HTTP transport.
+#[derive(Clone)]
+pub struct User(pub String);
+
+/// What templates can read besides the tool input.
+///
+/// Variables, allowlisted environment variables and secrets are read once at startup,
+/// `now` and `request` are filled in on every call.
+pub struct TemplateContext {
+    vars: Value,
+    env: Value,
+    secrets: Value,
+    secret_values: Vec<String>,
+}
+
+impl TemplateContext {
+    const REDACTED: &'static str = "[REDACTED]";
+
+    pub fn new(config: &DynamicMCPConfig) -> Self {
+        let vars = config
+            .variables
+            .clone()
+            .map(|variables| variables.into_iter().collect::<Map<String, Value>>())
+            .unwrap_or_default();
+
+        // Unset variables are left out, so templates can fall back on them with `default`
+        let env = config
+            .env_allowlist
+            .iter()
+            .flatten()
+            .filter_map(|name| {
+                std::env::var(name)
+                    .ok()
+                    .map(|value| (name.clone(), Value::String(value)))
+            })
+            .collect::<Map<String, Value>>();
+
+        let secrets = config
+            .secrets
+            .iter()
+            .flatten()
+            .map(|(name, path)| {
+                let value = std::fs::read_to_string(path)
+                    .unwrap_or_else(|err| panic!("Error while reading secret {}: {}", name, err));
+                let value = value.trim_end_matches(['\r', '\n']).to_string();
+                (name.clone(), Value::String(value))
+            })
+            .collect::<Map<String, Value>>();
+
+        let mut secret_values: Vec<String> = secrets
+            .values()
+            .filter_map(Value::as_str)
+            .filter(|value| !value.is_empty())
+            // Secrets rendered in a URL show up encoded in errors
+            .flat_map(|value| [value.to_string(), urlencoding::encode(value).into_owned()])
+            .collect();
+        secret_values.dedup();
+        // Longest first, so a secret containing another one is redacted whole
+        secret_values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+
+        Self {
+            vars: Value::Object(vars),
+            env: Value::Object(env),
+            secrets: Value::Object(secrets),
+            secret_values,
+        }
+    }
+
+    fn now() -> Value {
+        let now = Utc::now();
+        json!({
+            "unix": now.timestamp(),
+            "unix_ms": now.timestamp_millis(),
+            "iso": now.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "date": now.format("%Y-%m-%d").to_string(),
+        })
+    }
+
+    fn request(request_context: &RequestContext<RoleServer>) -> Value {
+        let client = request_context.peer.peer_info().map(|peer_info| {
+            json!({
+                "name": peer_info.client_info.name,
+                "version": peer_info.client_info.version,
+            })
+        });
+        let session_id = request_context
+            .extensions
+            .get::<SessionId>()
+            .map(|session_id| session_id.0.clone());
+
+        json!({
+            "id": request_context.id,
+            "session_id": session_id,
+            "client": client,
+            "user": Self::user(&request_context.extensions),
+        })
+    }
+
+    /// Returns the user the transport authenticated the call as, if any.
+    fn user(extensions: &Extensions) -> Option<String> {
+        extensions
+            .get::<User>()
+            .or_else(|| {
+                extensions
+                    .get::<http::request::Parts>()
+                    .and_then(|parts| parts.extensions.get::<User>())
+            })
+            .map(|user| user.0.clone())
+    }
+
+    fn with_request(&self, input: Value, request: Value) -> Value {
+        json!({
+            Template::INPUT_NAME: input,
+            Template::VARS_NAME: self.vars,
+            Template::ENV_NAME: self.env,
+            Template::SECRETS_NAME: self.secrets,
+            Template::NOW_NAME: Self::now(),
+            Template::REQUEST_NAME: request,
+        })
+    }
+
+    /// Returns the template context of a call with the given input.
+    pub fn build(&self, input: Value, request_context: &RequestContext<RoleServer>) -> Value {
+        self.with_request(input, Self::request(request_context))
+    }
+
+    /// Returns the template context of a call made from the command line, outside of any session.
+    pub fn preview(&self, input: Value) -> Value {
+        let request = json!({
+            "id": 0,
+            "session_id": "preview",
+            "client": {
+                "name": "easymcp",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "user": null,
+        });
+        self.with_request(input, request)
+    }
+
+    /// Replaces secret values in `text` with a marker.
+    pub fn redact(&self, text: &mut String) {
+        for secret in self.secret_values.iter() {
+            if text.contains(secret.as_str()) {
+                *text = text.replace(secret.as_str(), Self::REDACTED);
+            }
+        }
+    }
+
+    /// Hides secret values in error messages, which may quote a rendered URL or command.
+    pub fn redact_errors(
+        &self,
+        mut result: Result<CallToolResult, ErrorData>,
+    ) -> Result<CallToolResult, ErrorData> {
+        if self.secret_values.is_empty() {
+            return result;
+        }
+
+        match result {
+            Ok(ref mut result) if result.is_error == Some(true) => {
+                for content in result.content.iter_mut().flatten() {
+                    if let RawContent::Text(ref mut text) = content.raw {
+                        self.redact(&mut text.text);
+                    }
+                }
+            }
+            Err(ref mut err) => {
+                let mut message = err.message.to_string();
+                self.redact(&mut message);
+                err.message = message.into();
+            }
+            Ok(_) => {}
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_comes_from_the_request_or_http_extensions() {
+        let mut extensions = Extensions::new();
+        assert_eq!(TemplateContext::user(&extensions), None);
+
+        let (parts, _) = http::Request::builder()
+            .extension(User("alice".to_string()))
+            .body(())
+            .unwrap()
+            .into_parts();
+        extensions.insert(parts);
+        assert_eq!(TemplateContext::user(&extensions).as_deref(), Some("alice"));
+
+        extensions.insert(User("bob".to_string()));
+        assert_eq!(TemplateContext::user(&extensions).as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn preview_has_no_user() {
+        let config: DynamicMCPConfig = serde_yaml::from_str("tools: []").unwrap();
+        let context = TemplateContext::new(&config).preview(json!({}));
+        assert_eq!(context["request"]["user"], Value::Null);
+        assert_eq!(context["request"]["session_id"], "preview");
+    }
+}
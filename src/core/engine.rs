@@ -1,24 +1,34 @@
+use crate::core::cache::ResponseCache;
+use crate::core::cassette::Cassette;
 use crate::core::closure::DynamicMCPClosure;
-use crate::core::config::{DynamicMCPConfig, HttpMethod, ToolData, ToolType};
-use crate::core::template::Template;
+use crate::core::command::CommandTool;
+use crate::core::config::{
+    DynamicMCPConfig, ErrorMode, LimitsConfig, ToolData, ToolMode, ToolType,
+};
+use crate::core::context::{SessionId, TemplateContext};
+use crate::core::http::HttpTool;
+use crate::core::jobs::{JobAction, JobStore};
+use crate::core::limit::{self, Limiter, Permits};
+use crate::core::output;
+use crate::core::progress::OutputNotifier;
+use crate::core::static_tool::StaticTool;
 use futures_core::future::BoxFuture;
-use lazy_static::lazy_static;
-use regex::{Captures, Regex};
-use reqwest::Body;
-use reqwest::header::{CONTENT_TYPE, HeaderValue};
-use rmcp::handler::server::tool::{Parameters, ToolRoute, ToolRouter};
+use rmcp::handler::server::tool::{Parameters, ToolCallContext, ToolRoute, ToolRouter};
 use rmcp::model::{
-    CallToolResult, Content, ErrorCode, Implementation, JsonObject, ServerCapabilities, ServerInfo,
-    Tool, ToolAnnotations,
+    CallToolRequestParam, CallToolResult, Implementation, JsonObject, ListToolsResult,
+    PaginatedRequestParam, ServerCapabilities, ServerInfo, Tool, ToolAnnotations,
 };
 use rmcp::serde_json::Value;
-use rmcp::{ErrorData, ServerHandler, tool_handler};
+use rmcp::service::RequestContext;
+use rmcp::{ErrorData, RoleServer, ServerHandler};
 use serde_json::json;
 use std::collections::HashMap;
-use std::process::Stdio;
-use std::str::FromStr;
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+
+type ToolCallInput = (Parameters<Value>, RequestContext<RoleServer>);
+type ToolFuture = BoxFuture<'static, Result<CallToolResult, ErrorData>>;
+type ToolRunner = Arc<dyn Fn(Value, CancellationToken) -> ToolFuture + Send + Sync>;
 
 #[derive(Clone)]
 pub struct DynamicMCP {
@@ -26,29 +36,89 @@ pub struct DynamicMCP {
     instruction: Option<String>,
     server_info: Option<Implementation>,
     server_capabilities: Option<ServerCapabilities>,
-}
-
-lazy_static! {
-    static ref ESCAPE_BRACKET_REGEX: Regex =
-        Regex::new(r"(\{\s*input\.\w+\s*})|(\{)").unwrap(); // This regex is used to escape the brackets in the template. For further details, see https://docs.rs/tinytemplate/latest/tinytemplate/syntax/index.html#escaping-curly-braces
+    error_mode: ErrorMode,
+    tool_limiters: Arc<HashMap<String, Limiter>>,
+    limiter: Option<Arc<Limiter>>,
+    session_limits: Option<LimitsConfig>,
+    session_limiter: Option<Arc<Limiter>>,
+    session_id: String,
 }
 
 impl DynamicMCP {
-    const URL_TEMPLATE_NAME: &'static str = "url";
-    const BODY_TEMPLATE_NAME: &'static str = "body";
-    const INPUT_NAME: &'static str = "input";
-    const COMMAND_TEMPLATE_NAME: &'static str = "command";
-    const STDIN_TEMPLATE_NAME: &'static str = "stdin";
-
     pub fn new(config: DynamicMCPConfig) -> Self {
+        let template_context = Arc::new(TemplateContext::new(&config));
+        let cassette = config
+            .cassette
+            .map(|cassette| Arc::new(Cassette::new(cassette, template_context.clone())));
+        let error_mode = config.error_mode.unwrap_or_default();
+        let sse_config = config
+            .transport_config
+            .and_then(|transport_config| transport_config.sse_config);
+        let limiter = sse_config
+            .as_ref()
+            .and_then(|sse_config| sse_config.limits.as_ref())
+            .and_then(|limits| Limiter::new("the server".to_string(), error_mode.clone(), limits))
+            .map(Arc::new);
+
         Self {
-            tool_router: Self::tool_router(config.tools),
+            tool_limiters: Arc::new(Self::tool_limiters(&config.tools, &error_mode)),
+            tool_router: Self::tool_router(
+                config.tools,
+                error_mode.clone(),
+                config.max_output_bytes,
+                template_context,
+                cassette,
+            ),
             instruction: config.instruction,
             server_info: config.server_info,
             server_capabilities: config.server_capabilities,
+            error_mode,
+            limiter,
+            session_limits: sse_config.and_then(|sse_config| sse_config.session_limits),
+            session_limiter: None,
+            session_id: Self::new_session_id(),
+        }
+    }
+
+    /// Returns a handler for a new client session, sharing the tools and the server-wide limits
+    /// but with its own session limits.
+    pub fn new_session(&self) -> Self {
+        let session_limiter = self
+            .session_limits
+            .as_ref()
+            .and_then(|limits| {
+                Limiter::new("this session".to_string(), self.error_mode.clone(), limits)
+            })
+            .map(Arc::new);
+
+        Self {
+            session_limiter,
+            session_id: Self::new_session_id(),
+            ..self.clone()
         }
     }
 
+    fn new_session_id() -> String {
+        uuid::Uuid::new_v4().simple().to_string()
+    }
+
+    fn tool_limiters(
+        tool_data: &[ToolData],
+        default_error_mode: &ErrorMode,
+    ) -> HashMap<String, Limiter> {
+        tool_data
+            .iter()
+            .filter_map(|entry| {
+                let error_mode = entry
+                    .error_mode
+                    .clone()
+                    .unwrap_or_else(|| default_error_mode.clone());
+                Limiter::new(format!("tool {}", entry.name), error_mode, &entry.limits())
+                    .map(|limiter| (entry.name.clone(), limiter))
+            })
+            .collect()
+    }
+
     fn generate_tool_description(
         description: String,
         name: String,
@@ -60,381 +130,354 @@ impl DynamicMCP {
             name: name.into(),
             description: Some(description.into()),
             input_schema: Arc::new(input_schema),
-            output_schema: output_schema.map(|schema| Arc::new(schema)),
+            output_schema: output_schema.map(Arc::new),
             annotations,
         }
     }
 
-    fn http_header_template_name(name: String) -> String {
-        format!("header_{}", name)
-    }
-
-    fn command_args_template_name(idx: usize) -> String {
-        format!("args_{}", idx)
-    }
-
-    fn sanitize_template_text(body_template: &str) -> String {
-        // Use a closure with `replace_all` for conditional replacement
-        let modified_string = ESCAPE_BRACKET_REGEX.replace_all(body_template, |caps: &Captures| {
-            // Check if the second group (the standalone '{') was captured
-            if caps.get(2).is_some() {
-                // If yes, replace it with '\{'
-                "\\{".to_string()
-            } else {
-                // Otherwise, it's a template variable (group 1).
-                // Return the original matched string to leave it unchanged.
-                caps[0].to_string()
-            }
-        });
+    fn general_http_method_template(
+        tool: HttpTool,
+        template_context: Arc<TemplateContext>,
+    ) -> impl Fn(ToolCallInput) -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
+        let tool = Arc::new(tool);
+
+        move |(Parameters(object), request_context): (
+            Parameters<Value>,
+            RequestContext<RoleServer>,
+        )|
+              -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
+            let tool = tool.clone();
+            let template_context = template_context.clone();
+            let context = template_context.build(object, &request_context);
 
-        modified_string.to_string()
+            Box::pin(async move {
+                let result = tool.call(&context, request_context.ct).await;
+                template_context.redact_errors(result)
+            })
+        }
     }
 
-    fn general_http_method_template(
-        tool_index: usize,
-        method: HttpMethod,
-        url: String,
-        body_template: Option<String>,
-        header_template: Option<HashMap<String, String>>,
-    ) -> impl Fn(Parameters<Value>) -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
-        // Initialize template once when the function is called
-        let mut template = Template::new();
-        template
-            .add_template(
-                Self::URL_TEMPLATE_NAME,
-                &Self::sanitize_template_text(url.as_str()),
-            )
-            .expect(format!("Error registering url template, tool index {}", tool_index).as_str());
-
-        let body_exist = if let Some(ref body_str) = body_template {
-            template
-                .add_template(
-                    Self::BODY_TEMPLATE_NAME,
-                    &Self::sanitize_template_text(body_str),
-                )
-                .expect(
-                    format!("Error registering body template, tool index {}", tool_index).as_str(),
-                );
-            true
-        } else {
-            false
-        };
+    fn general_command_template(
+        tool: CommandTool,
+        tool_name: String,
+        template_context: Arc<TemplateContext>,
+    ) -> impl Fn(ToolCallInput) -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
+        let tool = Arc::new(tool);
+
+        move |(Parameters(object), request_context): (
+            Parameters<Value>,
+            RequestContext<RoleServer>,
+        )|
+              -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
+            let tool = tool.clone();
+            let template_context = template_context.clone();
+            let context = template_context.build(object, &request_context);
+            let notifier = OutputNotifier::new(tool.stream_output(), &tool_name, &request_context);
 
-        let header_template = header_template.unwrap_or(HashMap::new());
-
-        // Prepare header templates
-        let header_template_names: HashMap<String, String> = header_template
-            .keys()
-            .map(|name| (name.clone(), Self::http_header_template_name(name.clone())))
-            .collect();
-
-        // Register header templates
-        for (header_name, template_name) in header_template_names.iter() {
-            if let Some(header_value) = header_template.get(header_name) {
-                template
-                    .add_template(template_name, &Self::sanitize_template_text(header_value))
-                    .expect(
-                        format!(
-                            "Error registering header template, tool index {}, header name {}",
-                            tool_index, header_name
-                        )
-                        .as_str(),
-                    );
-            }
+            Box::pin(async move {
+                let result = tool.call(&context, request_context.ct, notifier).await;
+                template_context.redact_errors(result)
+            })
         }
+    }
 
-        // Move the initialized template and other data into the closure
-        move |Parameters(object): Parameters<Value>| -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
-            // Clone all the captured variables for use in the async block
-            let method = method.clone();
-            let template = template.clone(); // Clone the pre-initialized template
-            let header_template_names = header_template_names.clone();
+    fn general_static_template(
+        tool: StaticTool,
+        template_context: Arc<TemplateContext>,
+    ) -> impl Fn(ToolCallInput) -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
+        let tool = Arc::new(tool);
+
+        move |(Parameters(object), request_context): (
+            Parameters<Value>,
+            RequestContext<RoleServer>,
+        )|
+              -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
+            let tool = tool.clone();
+            let template_context = template_context.clone();
+            let context = template_context.build(object, &request_context);
 
             Box::pin(async move {
-                let context = json!({
-                    Self::INPUT_NAME: object
-                });
+                let result = tool.call(&context, request_context.ct).await;
+                template_context.redact_errors(result)
+            })
+        }
+    }
 
-                // Render headers
-                let mut headers = reqwest::header::HeaderMap::new();
-                for (name, template_name) in header_template_names.iter() {
-                    let rendered_value = template.render(template_name, &context).map_err(|err| ErrorData::new(
-                        ErrorCode::PARSE_ERROR,
-                        format!("Error while rendering header template, header name {} : {}", name, err.to_string()),
-                        None,
-                    ))?;
-                    let header_name = reqwest::header::HeaderName::from_str(name).unwrap();
-                    let header_value = reqwest::header::HeaderValue::from_str(&rendered_value).unwrap();
-                    headers.insert(header_name, header_value);
-                }
+    fn http_runner(tool: HttpTool) -> ToolRunner {
+        let tool = Arc::new(tool);
+        Arc::new(move |context, ct| {
+            let tool = tool.clone();
+            Box::pin(async move { tool.call(&context, ct).await })
+        })
+    }
 
-                // Render URL
-                let rendered_url = template.render(Self::URL_TEMPLATE_NAME, &context).map_err(|err| ErrorData::new(
-                    ErrorCode::PARSE_ERROR,
-                    format!("Error while rendering url template: {}", err.to_string()),
-                    None,
-                ))?;
-
-                // Render body if exists
-                let rendered_body = if body_exist {
-                    let temp = template.render(Self::BODY_TEMPLATE_NAME, &context).map_err(|err| ErrorData::new(
-                            ErrorCode::PARSE_ERROR,
-                            format!("Error while rendering body template: {}", err.to_string()),
-                            None,
-                        ))?;
-                    Some(temp)
-                } else {
-                    None
-                };
+    fn command_runner(tool: CommandTool) -> ToolRunner {
+        let tool = Arc::new(tool);
+        Arc::new(move |context, ct| {
+            let tool = tool.clone();
+            // Nobody waits on a background job's request, so its output is not streamed
+            Box::pin(async move { tool.call(&context, ct, None).await })
+        })
+    }
 
-                let rendered_headers = headers;
+    fn static_runner(tool: StaticTool) -> ToolRunner {
+        let tool = Arc::new(tool);
+        Arc::new(move |context, ct| {
+            let tool = tool.clone();
+            Box::pin(async move { tool.call(&context, ct).await })
+        })
+    }
 
-                // Now build the request without holding the template
-                let client = reqwest::Client::new();
-                let mut req = match method {
-                    HttpMethod::GET => client.get(rendered_url.clone()),
-                    HttpMethod::POST => client.post(rendered_url.clone()),
-                    HttpMethod::PUT => client.put(rendered_url.clone()),
-                    HttpMethod::DELETE => client.delete(rendered_url.clone()),
-                };
+    fn general_background_template(
+        runner: ToolRunner,
+        jobs: Arc<JobStore>,
+        template_context: Arc<TemplateContext>,
+    ) -> impl Fn(ToolCallInput) -> ToolFuture {
+        move |(Parameters(object), request_context): ToolCallInput| -> ToolFuture {
+            let runner = runner.clone();
+            let jobs = jobs.clone();
+            let template_context = template_context.clone();
+            let context = template_context.build(object, &request_context);
+            // The job keeps the call's concurrency slots until it finishes
+            let permits = request_context.extensions.get::<Permits>().cloned();
 
-                if let Some(body) = rendered_body {
-                    req = req.body(Body::from(body));
-                }
+            Box::pin(async move {
+                jobs.start(move |ct| {
+                    Box::pin(async move {
+                        let _permits = permits;
+                        let result = runner(context, ct).await;
+                        template_context.redact_errors(result)
+                    })
+                })
+                .await
+            })
+        }
+    }
 
-                req = req.headers(rendered_headers);
+    fn general_job_template(
+        jobs: Arc<JobStore>,
+        action: JobAction,
+    ) -> impl Fn(Parameters<Value>) -> ToolFuture {
+        move |Parameters(object): Parameters<Value>| -> ToolFuture {
+            let jobs = jobs.clone();
 
-                let res = req.send().await.map_err(|err| {
-                    ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Error while sending a request to {}: {}", rendered_url, err),
-                        None,
-                    )
-                })?;
+            Box::pin(async move { jobs.call(action, &object).await })
+        }
+    }
 
+    /// Route for a tool that starts a background job and returns its id instead of the result.
+    fn background_route(
+        mut tool_description: Tool,
+        runner: ToolRunner,
+        jobs: Arc<JobStore>,
+        template_context: Arc<TemplateContext>,
+    ) -> ToolRoute<DynamicMCP> {
+        let name = tool_description.name.clone();
+        tool_description.description = Some(
+            format!(
+                "{} Runs in the background and returns a job id, pass it to {} to follow the job, {} to get its result and {} to stop it.",
+                tool_description.description.unwrap_or_default(),
+                JobAction::Status.tool_name(&name),
+                JobAction::Result.tool_name(&name),
+                JobAction::Cancel.tool_name(&name),
+            )
+            .into(),
+        );
 
-                let response_status = res.status().as_u16();
+        // The call answers with a job id, the output schema describes what `_result` returns.
+        tool_description.output_schema = None;
 
-                let empty_header_value = HeaderValue::from_static("");
+        let closure = Self::general_background_template(runner, jobs, template_context);
+        ToolRoute::new(tool_description, DynamicMCPClosure::new(closure))
+    }
 
-                let content_type = res.headers().get(CONTENT_TYPE).unwrap_or(&empty_header_value).to_str().unwrap_or("").to_string();
+    /// Route for a tool answering within the call.
+    ///
+    /// MCP requires tools declaring an output schema to return structured content, so their
+    /// successful results carry the JSON object they return as structured content too.
+    fn sync_route(
+        tool_description: Tool,
+        closure: impl Fn(ToolCallInput) -> ToolFuture + Send + Sync + 'static,
+    ) -> ToolRoute<DynamicMCP> {
+        if tool_description.output_schema.is_none() {
+            return ToolRoute::new(tool_description, DynamicMCPClosure::new(closure));
+        }
 
-                let content_length = res.content_length().unwrap_or(0);
+        let tool_name = tool_description.name.to_string();
+        let closure = move |input: ToolCallInput| -> ToolFuture {
+            let result = closure(input);
+            let tool_name = tool_name.clone();
+            Box::pin(async move {
+                result
+                    .await
+                    .map(|result| output::structured_content(&tool_name, result))
+            })
+        };
+        ToolRoute::new(tool_description, DynamicMCPClosure::new(closure))
+    }
 
-                let res_text = res.text().await.map_err(|err| {
-                    ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Error while reading content from {}: {}", rendered_url, err),
-                        None,
-                    )
+    /// Routes of the `<tool>_status`, `<tool>_result` and `<tool>_cancel` companion tools.
+    fn job_routes(tool_name: &str, jobs: Arc<JobStore>) -> Vec<ToolRoute<DynamicMCP>> {
+        JobAction::ALL
+            .iter()
+            .map(|action| {
+                let input_schema = json!({
+                    "type": "object",
+                    "properties": {
+                        "job_id": {
+                            "type": "string",
+                            "description": format!("Job id returned by {}", tool_name),
+                        }
+                    },
+                    "required": ["job_id"],
                 });
 
-                if res_text.is_err() && content_length > 0 {
-                    return Err(res_text.err().unwrap());
-                }
-
-                let res_val = res_text.unwrap();
-
-                let res_val = if content_type.contains("application/json") {
-                    serde_json::from_str::<Value>(&res_val).map_err(|err| {
-                        ErrorData::new(
-                            ErrorCode::INTERNAL_ERROR,
-                            format!("Error while parsing json content from {}: {}", rendered_url, err),
-                            None,
-                        )
-                    })?
-                } else {
-                    Value::String(res_val)
-                };
+                let tool_description = Self::generate_tool_description(
+                    action.description(tool_name),
+                    action.tool_name(tool_name),
+                    input_schema.as_object().cloned().unwrap_or_default(),
+                    None,
+                    None,
+                );
 
-                match response_status {
-                    200..=299 => (),
-                    _ => return Err(ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Error while sending a request to {}, got status code : {}, response body : {}", rendered_url, response_status, res_val.to_string()),
-                        None,
-                    ))
-                }
+                let closure = Self::general_job_template(jobs.clone(), *action);
+                ToolRoute::new(tool_description, DynamicMCPClosure::new(closure))
+            })
+            .collect()
+    }
 
-                let content = Content::json::<Value>(res_val).map_err(|err| {
-                    ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Error while parsing content as json: {}", err),
-                        None,
+    /// Returns non-fatal problems found in a config, such as inputs open to shell injection.
+    ///
+    /// Fatal problems already panic when the tools are built.
+    pub fn validation_warnings(config: &DynamicMCPConfig) -> Vec<String> {
+        config
+            .tools
+            .iter()
+            .filter_map(|tool| {
+                tool.command_metadata.as_ref().map(|command_metadata| {
+                    (
+                        tool.name.as_str(),
+                        command_metadata,
+                        tool.template_mode.unwrap_or_default(),
                     )
-                })?;
-
-                Ok(CallToolResult::success(vec![content]))
+                })
             })
-        }
+            .flat_map(|(name, command_metadata, template_mode)| {
+                CommandTool::validation_warnings(name, command_metadata, template_mode)
+            })
+            .collect()
     }
 
-    fn general_command_template(
-        tool_index: usize,
-        command_template: String,
-        args_template: Option<Vec<String>>,
-        stdin_template: Option<String>,
-    ) -> impl Fn(Parameters<Value>) -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
-        // Initialize template once when the function is called
-        let mut template = Template::new();
-        template
-            .add_template(
-                Self::COMMAND_TEMPLATE_NAME,
-                &Self::sanitize_template_text(command_template.as_str()),
-            )
-            .expect(
-                format!(
-                    "Error registering command template, tool index {}: {}",
-                    tool_index, command_template
-                )
-                .as_str(),
-            );
-
-        let stdin_template_exist = if let Some(ref stdin_template) = stdin_template {
-            template
-                .add_template(
-                    Self::STDIN_TEMPLATE_NAME,
-                    &Self::sanitize_template_text(stdin_template),
-                )
-                .expect(
-                    format!(
-                        "Error registering stdin template, tool index {}",
-                        tool_index
-                    )
-                    .as_str(),
-                );
-            true
-        } else {
-            false
+    /// Renders the HTTP request, command line or static reply a call to `tool_name` with `input`
+    /// would make, without making it. Secrets are redacted.
+    pub fn render_preview(
+        config: &DynamicMCPConfig,
+        tool_name: &str,
+        input: Value,
+    ) -> Result<String, String> {
+        let Some((i, entry)) = config
+            .tools
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.name == tool_name)
+        else {
+            return Err(format!("Unknown tool {}", tool_name));
         };
 
-        let args_template = args_template.unwrap_or(vec![]);
-        for (i, args) in args_template.iter().enumerate() {
-            let template_name = Self::command_args_template_name(i);
-            template
-                .add_template(&template_name, &Self::sanitize_template_text(args))
-                .expect(
-                    format!(
-                        "Error registering args template, tool index {}, arg index {}",
-                        tool_index, i
-                    )
-                    .as_str(),
-                );
-        }
-
-        move |Parameters(object): Parameters<Value>| -> BoxFuture<'static, Result<CallToolResult, ErrorData>> {
-            let template = template.clone(); // Clone the pre-initialized template
-            let args_template = args_template.clone();
-
-            Box::pin(async move {
-                let context = json!({
-                    Self::INPUT_NAME: object
-                });
+        let template_context = TemplateContext::new(config);
+        let context = template_context.preview(input);
+        let error_mode = ErrorMode::default();
+        let template_mode = entry.template_mode.unwrap_or_default();
 
-                let rendered_command = template.render(Self::COMMAND_TEMPLATE_NAME, &context).map_err(|err| ErrorData::new(
-                    ErrorCode::PARSE_ERROR,
-                    format!("Error while rendering command template: {}", err.to_string()),
+        let rendered = match entry.tool_type {
+            ToolType::HTTP => {
+                let Some(ref http_metadata) = entry.http_metadata else {
+                    return Err(format!("Tool {} has no http_metadata", tool_name));
+                };
+                let tool = HttpTool::new(
+                    i,
+                    http_metadata.clone(),
+                    error_mode,
                     None,
-                ))?;
-
-                let args_template = args_template.iter().enumerate().map(|(i,_)| template.render(&Self::command_args_template_name(i), &context).map_err(|err| ErrorData::new(
-                    ErrorCode::PARSE_ERROR,
-                    format!("Error while rendering args template: {}", err.to_string()),
                     None,
-                ))).collect::<Result<Vec<String>, ErrorData>>()?;
-
-                let mut command = tokio::process::Command::new(rendered_command);
-
-                if stdin_template_exist {
-                    command.stdin(Stdio::piped());
-                }
-
-                let mut command = command
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .args(&args_template)
-                    .spawn()
-                    .map_err(|err| ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Error while spawning a process: {}", err),
-                        None,
-                    ))?;
-
-                if stdin_template_exist {
-                    let mut stdin = match command.stdin.take() {
-                        Some(stdin) => stdin,
-                        None => return Err(ErrorData::new(
-                            ErrorCode::INTERNAL_ERROR,
-                            "Error while spawning a process: stdin is None".to_string(),
-                            None
-                        ))
-                    };
-
-                    let stdin_data = template.render(Self::STDIN_TEMPLATE_NAME, &context).map_err(|err| ErrorData::new(
-                        ErrorCode::PARSE_ERROR,
-                        format!("Error while rendering stdin template: {}", err.to_string()),
-                        None,
-                    ))?;
-                    stdin.write_all(stdin_data.as_bytes()).await.map_err(|err| ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Error while writing stdin: {}", err),
-                        None
-                    ))?;
-                }
-
-                let output = command.wait_with_output().await.map_err(|err| ErrorData::new(
-                    ErrorCode::INTERNAL_ERROR,
-                    format!("Error while waiting for a process: {}", err),
+                    template_mode,
                     None,
-                ))?;
-
-                let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-
-                let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-
-                if !output.status.success() {
-                    return Err(ErrorData::new(
-                        ErrorCode::INTERNAL_ERROR,
-                        format!("Error while executing a command: {}", stderr),
-                        None,
-                    ))
-                }
-
-                if let Ok(json_output) = serde_json::from_str::<Value>(&stdout) {
-                    let content = Content::json::<Value>(json_output).map_err(|err| {
-                        ErrorData::new(
-                            ErrorCode::INTERNAL_ERROR,
-                            format!("Error while parsing content as json: {}", err),
-                            None,
-                        )
-                    })?;
-                    return Ok(CallToolResult::success(vec![content]));
-                }
-
-                Ok(CallToolResult::success(vec![Content::text(stdout)]))
-            })
+                );
+                tool.render(&context).map(|request| request.to_string())
+            }
+            ToolType::COMMAND => {
+                let Some(ref command_metadata) = entry.command_metadata else {
+                    return Err(format!("Tool {} has no command_metadata", tool_name));
+                };
+                let tool = CommandTool::new(
+                    i,
+                    command_metadata.clone(),
+                    error_mode,
+                    None,
+                    None,
+                    template_mode,
+                );
+                tool.render(&context).map(|command| tool.preview(&command))
+            }
+            ToolType::STATIC => {
+                let Some(ref static_metadata) = entry.static_metadata else {
+                    return Err(format!("Tool {} has no static_metadata", tool_name));
+                };
+                let tool = StaticTool::new(i, static_metadata.clone(), error_mode, template_mode);
+                tool.render(&context).map(|reply| tool.preview(&reply))
+            }
+        };
 
-        }
+        // Errors may quote rendered values too
+        let redact = |mut text: String| {
+            template_context.redact(&mut text);
+            text
+        };
+        rendered
+            .map(redact)
+            .map_err(|err| redact(err.message.to_string()))
     }
 
-    pub fn tool_router(tool_data: Vec<ToolData>) -> ToolRouter<DynamicMCP> {
+    pub fn tool_router(
+        tool_data: Vec<ToolData>,
+        default_error_mode: ErrorMode,
+        default_max_output_bytes: Option<u64>,
+        template_context: Arc<TemplateContext>,
+        cassette: Option<Arc<Cassette>>,
+    ) -> ToolRouter<DynamicMCP> {
         let mut router = ToolRouter::new();
 
         for (i, entry) in tool_data.iter().enumerate() {
-            let (function_tool, tool_description) = match entry.tool_type {
+            let error_mode = entry
+                .error_mode
+                .clone()
+                .unwrap_or_else(|| default_error_mode.clone());
+            let cache = entry
+                .cache
+                .clone()
+                .map(|cache_config| ResponseCache::new(entry.name.clone(), cache_config));
+            let max_output_bytes = entry.max_output_bytes.or(default_max_output_bytes);
+            let jobs = (entry.mode == Some(ToolMode::BACKGROUND)).then(|| {
+                Arc::new(JobStore::new(
+                    entry.name.clone(),
+                    error_mode.clone(),
+                    entry.background.clone().unwrap_or_default(),
+                ))
+            });
+            let tool_route = match entry.tool_type {
                 ToolType::HTTP => {
                     let Some(ref http_metadata) = entry.http_metadata else {
                         continue;
                     };
-                    let method = http_metadata.method.clone();
-                    let url = http_metadata.url.clone();
-                    let body_template = http_metadata.body.clone();
-                    let headers = http_metadata.headers.clone();
-
-                    let closure =
-                        Self::general_http_method_template(i, method, url, body_template, headers);
-                    let function_tool = DynamicMCPClosure::new(closure);
-
+                    let tool = HttpTool::new(
+                        i,
+                        http_metadata.clone(),
+                        error_mode,
+                        max_output_bytes,
+                        cache,
+                        entry.template_mode.unwrap_or_default(),
+                        cassette.clone(),
+                    );
                     let tool_description = Self::generate_tool_description(
                         entry.description.clone(),
                         entry.name.clone(),
@@ -443,25 +486,33 @@ impl DynamicMCP {
                         entry.tool_annotations.clone(),
                     );
 
-                    (function_tool, tool_description)
+                    match jobs {
+                        Some(ref jobs) => Self::background_route(
+                            tool_description,
+                            Self::http_runner(tool),
+                            jobs.clone(),
+                            template_context.clone(),
+                        ),
+                        None => {
+                            let closure =
+                                Self::general_http_method_template(tool, template_context.clone());
+                            Self::sync_route(tool_description, closure)
+                        }
+                    }
                 }
 
                 ToolType::COMMAND => {
                     let Some(ref command_metadata) = entry.command_metadata else {
                         continue;
                     };
-                    let command_template = command_metadata.command.clone();
-                    let args_template = command_metadata.args.clone();
-                    let stdin_template = command_metadata.stdin.clone();
-
-                    let closure = Self::general_command_template(
+                    let tool = CommandTool::new(
                         i,
-                        command_template,
-                        args_template,
-                        stdin_template,
+                        command_metadata.clone(),
+                        error_mode,
+                        max_output_bytes,
+                        cache,
+                        entry.template_mode.unwrap_or_default(),
                     );
-                    let function_tool = DynamicMCPClosure::new(closure);
-
                     let tool_description = Self::generate_tool_description(
                         entry.description.clone(),
                         entry.name.clone(),
@@ -470,19 +521,108 @@ impl DynamicMCP {
                         entry.tool_annotations.clone(),
                     );
 
-                    (function_tool, tool_description)
+                    match jobs {
+                        Some(ref jobs) => Self::background_route(
+                            tool_description,
+                            Self::command_runner(tool),
+                            jobs.clone(),
+                            template_context.clone(),
+                        ),
+                        None => {
+                            let closure = Self::general_command_template(
+                                tool,
+                                entry.name.clone(),
+                                template_context.clone(),
+                            );
+                            Self::sync_route(tool_description, closure)
+                        }
+                    }
+                }
+
+                ToolType::STATIC => {
+                    let Some(ref static_metadata) = entry.static_metadata else {
+                        continue;
+                    };
+                    let tool = StaticTool::new(
+                        i,
+                        static_metadata.clone(),
+                        error_mode,
+                        entry.template_mode.unwrap_or_default(),
+                    );
+                    let tool_description = Self::generate_tool_description(
+                        entry.description.clone(),
+                        entry.name.clone(),
+                        static_metadata.input_schema.clone(),
+                        static_metadata.output_schema.clone(),
+                        entry.tool_annotations.clone(),
+                    );
+
+                    match jobs {
+                        Some(ref jobs) => Self::background_route(
+                            tool_description,
+                            Self::static_runner(tool),
+                            jobs.clone(),
+                            template_context.clone(),
+                        ),
+                        None => {
+                            let closure =
+                                Self::general_static_template(tool, template_context.clone());
+                            Self::sync_route(tool_description, closure)
+                        }
+                    }
                 }
             };
 
-            router = router.with_route(ToolRoute::new(tool_description, function_tool));
+            router = router.with_route(tool_route);
+
+            if let Some(jobs) = jobs {
+                for job_route in Self::job_routes(&entry.name, jobs) {
+                    router = router.with_route(job_route);
+                }
+            }
         }
 
         router
     }
 }
 
-#[tool_handler]
 impl ServerHandler for DynamicMCP {
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        mut context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, ErrorData> {
+        // Most specific first, so a call rejected by its tool does not use up a server-wide token
+        let limiters: Vec<&Limiter> = [
+            self.tool_limiters.get(request.name.as_ref()),
+            self.session_limiter.as_deref(),
+            self.limiter.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        let permits = match limit::admit(&limiters, &context.ct).await {
+            Ok(permits) => permits,
+            Err(rejection) => return rejection.into_result(),
+        };
+
+        context
+            .extensions
+            .insert(SessionId(self.session_id.clone()));
+        context.extensions.insert(permits);
+        let tcc = ToolCallContext::new(self, request, context);
+        self.tool_router.call(tcc).await
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, ErrorData> {
+        Ok(ListToolsResult::with_all_items(self.tool_router.list_all()))
+    }
+
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             instructions: self.instruction.clone(),
@@ -495,3 +635,113 @@ impl ServerHandler for DynamicMCP {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> DynamicMCPConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn render_preview_shows_the_http_request_with_secrets_redacted() {
+        let secret_path =
+            std::env::temp_dir().join(format!("easymcp-render-secret-{}", std::process::id()));
+        std::fs::write(&secret_path, "s3cr3t\n").unwrap();
+        let config = config(&format!(
+            r#"
+secrets:
+  token: {}
+tools:
+  - name: search
+    description: search
+    tool_type: HTTP
+    http_metadata:
+      url: "https://example.com/search?q={{ input.q }}"
+      method: POST
+      headers:
+        Authorization: "Bearer {{ secrets.token }}"
+      body: '{{"q": "{{ input.q }}"}}'
+      input_schema: {{}}
+"#,
+            secret_path.display()
+        ));
+
+        let preview = DynamicMCP::render_preview(&config, "search", json!({"q": "rust"})).unwrap();
+        std::fs::remove_file(&secret_path).unwrap();
+        assert_eq!(
+            preview,
+            "POST https://example.com/search?q=rust\nauthorization: Bearer [REDACTED]\n\n{\"q\": \"rust\"}"
+        );
+    }
+
+    #[test]
+    fn render_preview_redacts_secrets_in_errors() {
+        let secret_path = std::env::temp_dir().join(format!(
+            "easymcp-render-error-secret-{}",
+            std::process::id()
+        ));
+        std::fs::write(&secret_path, "s3cr3t").unwrap();
+        let config = config(&format!(
+            r#"
+secrets:
+  token: {}
+tools:
+  - name: broken
+    description: broken
+    tool_type: HTTP
+    template_mode: FULL
+    http_metadata:
+      url: "https://example.com/{{{{ secrets.token | date }}}}"
+      method: GET
+      input_schema: {{}}
+"#,
+            secret_path.display()
+        ));
+
+        let err = DynamicMCP::render_preview(&config, "broken", json!({})).unwrap_err();
+        std::fs::remove_file(&secret_path).unwrap();
+        assert!(err.contains("got \"[REDACTED]\""), "{}", err);
+        assert!(!err.contains("s3cr3t"), "{}", err);
+    }
+
+    #[test]
+    fn render_preview_shows_the_command_line() {
+        let config = config(
+            r#"
+tools:
+  - name: grep
+    description: grep
+    tool_type: COMMAND
+    command_metadata:
+      command: grep
+      args: ["-n", "{ input.pattern }", "/var/log/app log"]
+      env:
+        MODE: "{ input.mode }"
+      input_schema: {}
+"#,
+        );
+
+        let preview =
+            DynamicMCP::render_preview(&config, "grep", json!({"pattern": "a b", "mode": "fast"}))
+                .unwrap();
+        let lines: Vec<&str> = preview.lines().collect();
+        assert!(lines[0].starts_with("Command: "), "{}", preview);
+        assert!(
+            lines[0].ends_with("grep -n 'a b' '/var/log/app log'"),
+            "{}",
+            preview
+        );
+        assert_eq!(lines[1], "Environment: MODE=fast");
+    }
+
+    #[test]
+    fn render_preview_rejects_unknown_tools() {
+        let config = config("tools: []");
+        assert_eq!(
+            DynamicMCP::render_preview(&config, "missing", json!({})).unwrap_err(),
+            "Unknown tool missing"
+        );
+    }
+}
@@ -0,0 +1,1006 @@
+use crate::core::cache::ResponseCache;
+use crate::core::cassette::Cassette;
+use crate::core::config::{ErrorMode, HttpMetadata, HttpMethod, PaginationConfig};
+use crate::core::output::{self, BoundedOutput};
+use crate::core::pagination::Paginator;
+use crate::core::template::{Template, TemplateMode};
+use reqwest::header::{
+    CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue,
+};
+use reqwest::{Body, Url};
+use rmcp::ErrorData;
+use rmcp::model::{CallToolResult, Content, ErrorCode};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// An HTTP request with all templates rendered, ready to be sent.
+#[derive(Debug, Clone)]
+pub struct RenderedHttpRequest {
+    pub method: HttpMethod,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Option<String>,
+}
+
+impl RenderedHttpRequest {
+    pub fn cache_key(&self) -> String {
+        let mut headers: Vec<String> = self
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}", name, value.to_str().unwrap_or("")))
+            .collect();
+        headers.sort();
+
+        let method = format!("{:?}", self.method);
+        let headers = headers.join("\n");
+        ResponseCache::key(&[
+            &method,
+            &self.url,
+            &headers,
+            self.body.as_deref().unwrap_or(""),
+        ])
+    }
+}
+
+/// Writes the request as the method and URL, the headers and the body, as in an HTTP message.
+impl std::fmt::Display for RenderedHttpRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} {}", self.method, self.url)?;
+
+        let mut headers: Vec<(&str, &str)> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_str().unwrap_or("")))
+            .collect();
+        headers.sort();
+        for (name, value) in headers {
+            write!(f, "\n{}: {}", name, value)?;
+        }
+
+        if let Some(ref body) = self.body {
+            write!(f, "\n\n{}", body)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct HttpTool {
+    template: Template,
+    method: HttpMethod,
+    body_exist: bool,
+    header_template_names: HashMap<String, String>,
+    envelope: bool,
+    header_allowlist: Vec<String>,
+    success_status_codes: Vec<u16>,
+    max_binary_bytes: u64,
+    max_output_bytes: Option<u64>,
+    pagination: Option<PaginationConfig>,
+    error_mode: ErrorMode,
+    cache: Option<ResponseCache>,
+    cassette: Option<Arc<Cassette>>,
+    base_url: Option<Url>,
+}
+
+impl HttpTool {
+    const URL_TEMPLATE_NAME: &'static str = "url";
+    const BODY_TEMPLATE_NAME: &'static str = "body";
+
+    fn http_header_template_name(name: String) -> String {
+        format!("header_{}", name)
+    }
+
+    pub fn new(
+        tool_index: usize,
+        http_metadata: HttpMetadata,
+        error_mode: ErrorMode,
+        max_output_bytes: Option<u64>,
+        cache: Option<ResponseCache>,
+        template_mode: TemplateMode,
+        cassette: Option<Arc<Cassette>>,
+    ) -> Self {
+        let HttpMetadata {
+            method,
+            url,
+            body: body_template,
+            headers: header_template,
+            response_config,
+            max_binary_bytes,
+            pagination,
+            ..
+        } = http_metadata;
+
+        // Initialize template once when the tool is created
+        let mut template = Template::new(template_mode);
+        template
+            .add_template(Self::URL_TEMPLATE_NAME, url.as_str())
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Error registering url template, tool index {}: {}",
+                    tool_index, err
+                )
+            });
+
+        let body_exist = if let Some(ref body_str) = body_template {
+            template
+                .add_template(Self::BODY_TEMPLATE_NAME, body_str)
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "Error registering body template, tool index {}: {}",
+                        tool_index, err
+                    )
+                });
+            true
+        } else {
+            false
+        };
+
+        let header_template = header_template.unwrap_or_default();
+
+        // Prepare header templates
+        let header_template_names: HashMap<String, String> = header_template
+            .keys()
+            .map(|name| (name.clone(), Self::http_header_template_name(name.clone())))
+            .collect();
+
+        // Register header templates
+        for (header_name, template_name) in header_template_names.iter() {
+            if let Some(header_value) = header_template.get(header_name) {
+                template
+                    .add_template(template_name, header_value)
+                    .unwrap_or_else(|err| {
+                        panic!(
+                            "Error registering header template, tool index {}, header name {}: {}",
+                            tool_index, header_name, err
+                        )
+                    });
+            }
+        }
+
+        let response_config = response_config.unwrap_or_default();
+
+        Self {
+            template,
+            method,
+            body_exist,
+            header_template_names,
+            envelope: response_config.envelope.unwrap_or(false),
+            header_allowlist: response_config
+                .header_allowlist
+                .unwrap_or_default()
+                .iter()
+                .map(|name| name.to_lowercase())
+                .collect(),
+            success_status_codes: response_config.success_status_codes.unwrap_or_default(),
+            max_binary_bytes: max_binary_bytes.unwrap_or(output::DEFAULT_MAX_BINARY_BYTES),
+            max_output_bytes,
+            pagination,
+            error_mode,
+            cache,
+            cassette,
+            base_url: None,
+        }
+    }
+
+    /// Sends every request of a call to `base_url` instead, keeping the path and query of its URL.
+    pub fn with_base_url(mut self, base_url: Url) -> Self {
+        self.base_url = Some(base_url);
+        self
+    }
+
+    /// Points `url` at the base URL, if one is set.
+    fn rebase(&self, url: &str) -> Result<String, String> {
+        let Some(ref base_url) = self.base_url else {
+            return Ok(url.to_string());
+        };
+        let mut rebased = Url::parse(url).map_err(|err| format!("Invalid url {}: {}", url, err))?;
+        rebased
+            .set_scheme(base_url.scheme())
+            .and_then(|_| rebased.set_host(base_url.host_str()).map_err(|_| ()))
+            .and_then(|_| rebased.set_port(base_url.port()))
+            .map_err(|_| format!("Cannot send {} to {}", url, base_url))?;
+        Ok(rebased.to_string())
+    }
+
+    /// Renders the request, leaving out headers and a body that render empty, so templates
+    /// can make them conditional.
+    pub fn render(&self, context: &Value) -> Result<RenderedHttpRequest, ErrorData> {
+        // Render headers
+        let mut headers = HeaderMap::new();
+        for (name, template_name) in self.header_template_names.iter() {
+            let rendered_value = self
+                .template
+                .render(template_name, context)
+                .map_err(|err| {
+                    ErrorData::new(
+                        ErrorCode::PARSE_ERROR,
+                        format!(
+                            "Error while rendering header template, header name {} : {}",
+                            name, err
+                        ),
+                        None,
+                    )
+                })?;
+            if rendered_value.is_empty() {
+                continue;
+            }
+            let header_name = HeaderName::from_str(name).map_err(|err| {
+                ErrorData::new(
+                    ErrorCode::INVALID_PARAMS,
+                    format!("Invalid header name {}: {}", name, err),
+                    None,
+                )
+            })?;
+            let header_value = HeaderValue::from_str(&rendered_value).map_err(|err| {
+                ErrorData::new(
+                    ErrorCode::INVALID_PARAMS,
+                    format!("Invalid value for header {}: {}", name, err),
+                    None,
+                )
+            })?;
+            headers.insert(header_name, header_value);
+        }
+
+        // Render URL
+        let url = self
+            .template
+            .render(Self::URL_TEMPLATE_NAME, context)
+            .map_err(|err| {
+                ErrorData::new(
+                    ErrorCode::PARSE_ERROR,
+                    format!("Error while rendering url template: {}", err),
+                    None,
+                )
+            })?;
+
+        // Render body if exists
+        let body = if self.body_exist {
+            let temp = self
+                .template
+                .render(Self::BODY_TEMPLATE_NAME, context)
+                .map_err(|err| {
+                    ErrorData::new(
+                        ErrorCode::PARSE_ERROR,
+                        format!("Error while rendering body template: {}", err),
+                        None,
+                    )
+                })?;
+            Some(temp).filter(|body| !body.is_empty())
+        } else {
+            None
+        };
+
+        Ok(RenderedHttpRequest {
+            method: self.method.clone(),
+            url,
+            headers,
+            body,
+        })
+    }
+
+    fn build_request(
+        client: &reqwest::Client,
+        request: &RenderedHttpRequest,
+        url: &str,
+    ) -> reqwest::RequestBuilder {
+        let mut req = match request.method {
+            HttpMethod::GET => client.get(url),
+            HttpMethod::POST => client.post(url),
+            HttpMethod::PUT => client.put(url),
+            HttpMethod::DELETE => client.delete(url),
+        };
+
+        if let Some(ref body) = request.body {
+            req = req.body(Body::from(body.clone()));
+        }
+
+        req.headers(request.headers.clone())
+    }
+
+    /// Sends one request of a call, through the cassette when recording or replaying, or to the
+    /// base URL when one is set.
+    async fn fetch(
+        &self,
+        client: &reqwest::Client,
+        request: &RenderedHttpRequest,
+        url: &str,
+    ) -> Result<reqwest::Response, String> {
+        let url = self.rebase(url)?;
+        let req = Self::build_request(client, request, &url);
+        match self.cassette {
+            Some(ref cassette) => {
+                // Enough to tell whether the body exceeds whichever limit the caller applies
+                let body_limit = self
+                    .max_output_bytes
+                    .map(|max_output_bytes| max_output_bytes.max(self.max_binary_bytes));
+                cassette.send(request, &url, req, body_limit).await
+            }
+            None => req.send().await.map_err(|err| err.to_string()),
+        }
+    }
+
+    /// Size of a response body as announced by the server, or as recorded before it was cut.
+    fn original_size(res: &reqwest::Response) -> Option<u64> {
+        res.headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+    }
+
+    /// Reads a response body, dropping the rest of the stream once `limit` bytes have been read.
+    async fn read_body(
+        res: &mut reqwest::Response,
+        limit: Option<u64>,
+    ) -> reqwest::Result<BoundedOutput> {
+        let mut body = BoundedOutput::default();
+        while let Some(chunk) = res.chunk().await? {
+            if body.push(&chunk, limit) {
+                break;
+            }
+        }
+        Ok(body)
+    }
+
+    pub async fn call(
+        &self,
+        context: &Value,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, ErrorData> {
+        let request = self.render(context)?;
+
+        let Some(ref cache) = self.cache else {
+            return self.execute(request, None, ct).await;
+        };
+
+        let cache_key = request.cache_key();
+        if let Some(result) = cache.get(&cache_key).await {
+            return Ok(result);
+        }
+
+        self.execute(request, Some(&cache_key), ct).await
+    }
+
+    /// Sends a rendered request and converts the response into a tool result.
+    ///
+    /// Successful results are stored in the response cache under `cache_key`, if given.
+    /// Requests still in flight when `ct` is cancelled are aborted by dropping them.
+    pub async fn execute(
+        &self,
+        request: RenderedHttpRequest,
+        cache_key: Option<&str>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, ErrorData> {
+        let url = request.url.clone();
+
+        tokio::select! {
+            result = self.send(request, cache_key) => result,
+            _ = ct.cancelled() => Err(ErrorData::new(
+                ErrorCode::INTERNAL_ERROR,
+                format!("Request to {} was cancelled", url),
+                None,
+            )),
+        }
+    }
+
+    async fn send(
+        &self,
+        request: RenderedHttpRequest,
+        cache_key: Option<&str>,
+    ) -> Result<CallToolResult, ErrorData> {
+        let error_mode = &self.error_mode;
+        let rendered_url = request.url.clone();
+
+        let client = reqwest::Client::new();
+        let mut res = match self.fetch(&client, &request, &rendered_url).await {
+            Ok(res) => res,
+            Err(err) => {
+                return output::execution_error(
+                    error_mode,
+                    format!("Error while sending a request to {}: {}", rendered_url, err),
+                );
+            }
+        };
+
+        let response_status = res.status().as_u16();
+
+        let empty_header_value = HeaderValue::from_static("");
+
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .unwrap_or(&empty_header_value)
+            .to_str()
+            .unwrap_or("")
+            .to_string();
+
+        let original_size = Self::original_size(&res);
+        let content_length = original_size.unwrap_or(0);
+
+        let first_page_headers = res.headers().clone();
+
+        let mut response_headers = serde_json::Map::new();
+        for (name, value) in res.headers().iter() {
+            if !self
+                .header_allowlist
+                .iter()
+                .any(|allowed| allowed == name.as_str())
+            {
+                continue;
+            }
+            response_headers.insert(
+                name.to_string(),
+                Value::String(value.to_str().unwrap_or("").to_string()),
+            );
+        }
+
+        let mut truncated = false;
+        let (res_val, media_content) = if output::is_binary_mime_type(&content_type) {
+            let res_body = Self::read_body(&mut res, Some(self.max_binary_bytes))
+                .await
+                .map_err(|err| {
+                    ErrorData::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        format!("Error while reading content from {}: {}", rendered_url, err),
+                        None,
+                    )
+                })?;
+
+            if res_body.truncated {
+                let size = original_size
+                    .map(|size| size.to_string())
+                    .unwrap_or_else(|| format!("more than {}", self.max_binary_bytes));
+                return output::execution_error(
+                    error_mode,
+                    format!(
+                        "Binary content from {} is {} bytes, exceeding the limit of {} bytes",
+                        rendered_url, size, self.max_binary_bytes
+                    ),
+                );
+            }
+
+            let res_bytes = res_body.data;
+
+            let placeholder = Value::String(format!(
+                "<{} bytes of {}>",
+                res_bytes.len(),
+                output::mime_essence(&content_type)
+            ));
+            (
+                placeholder,
+                Some(output::binary_content(
+                    &res_bytes,
+                    &content_type,
+                    &rendered_url,
+                )),
+            )
+        } else {
+            let res_body = Self::read_body(&mut res, self.max_output_bytes)
+                .await
+                .map_err(|err| {
+                    ErrorData::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        format!("Error while reading content from {}: {}", rendered_url, err),
+                        None,
+                    )
+                });
+
+            if res_body.is_err() && content_length > 0 {
+                return Err(res_body.err().unwrap());
+            }
+
+            let res_body = res_body.unwrap_or_default();
+            let res_val = String::from_utf8_lossy(&res_body.data).to_string();
+
+            truncated = res_body.truncated;
+            let res_val = if truncated {
+                // A truncated body is no longer valid JSON, so it is returned as marked text
+                Value::String(
+                    res_body.to_text(self.max_output_bytes.unwrap_or_default(), original_size),
+                )
+            } else if content_type.contains("application/json") {
+                serde_json::from_str::<Value>(&res_val).map_err(|err| {
+                    ErrorData::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        format!(
+                            "Error while parsing json content from {}: {}",
+                            rendered_url, err
+                        ),
+                        None,
+                    )
+                })?
+            } else {
+                Value::String(res_val)
+            };
+
+            (res_val, None)
+        };
+
+        match response_status {
+            200..=299 => (),
+            status if self.success_status_codes.contains(&status) => (),
+            _ => {
+                return output::execution_error(
+                    error_mode,
+                    format!(
+                        "Error while sending a request to {}, got status code : {}, response body : {}",
+                        rendered_url, response_status, res_val
+                    ),
+                );
+            }
+        }
+
+        let res_val = match self.pagination.clone() {
+            Some(pagination) if media_content.is_none() && !truncated => {
+                let mut paginator = Paginator::new(pagination);
+                let mut next_url =
+                    match paginator.add_page(&rendered_url, &first_page_headers, res_val) {
+                        Ok(next_url) => next_url,
+                        Err(message) => return output::execution_error(error_mode, message),
+                    };
+
+                while let Some(page_url) = next_url {
+                    let mut res = match self.fetch(&client, &request, &page_url).await {
+                        Ok(res) => res,
+                        Err(err) => {
+                            return output::execution_error(
+                                error_mode,
+                                format!("Error while sending a request to {}: {}", page_url, err),
+                            );
+                        }
+                    };
+
+                    let page_status = res.status().as_u16();
+                    let page_size = Self::original_size(&res);
+                    let page_headers = res.headers().clone();
+                    let page_body = Self::read_body(&mut res, self.max_output_bytes)
+                        .await
+                        .map_err(|err| {
+                            ErrorData::new(
+                                ErrorCode::INTERNAL_ERROR,
+                                format!("Error while reading page {}: {}", page_url, err),
+                                None,
+                            )
+                        })?;
+                    let page_text =
+                        page_body.to_text(self.max_output_bytes.unwrap_or_default(), page_size);
+
+                    if !res.status().is_success()
+                        && !self.success_status_codes.contains(&page_status)
+                    {
+                        return output::execution_error(
+                            error_mode,
+                            format!(
+                                "Error while sending a request to {}, got status code : {}, response body : {}",
+                                page_url, page_status, page_text
+                            ),
+                        );
+                    }
+
+                    if page_body.truncated {
+                        return output::execution_error(
+                            error_mode,
+                            format!(
+                                "Page {} exceeds the output limit of {} bytes",
+                                page_url,
+                                self.max_output_bytes.unwrap_or_default()
+                            ),
+                        );
+                    }
+
+                    let page_val = serde_json::from_str::<Value>(&page_text).map_err(|err| {
+                        ErrorData::new(
+                            ErrorCode::INTERNAL_ERROR,
+                            format!(
+                                "Error while parsing json content from {}: {}",
+                                page_url, err
+                            ),
+                            None,
+                        )
+                    })?;
+
+                    next_url = match paginator.add_page(&page_url, &page_headers, page_val) {
+                        Ok(next_url) => next_url,
+                        Err(message) => return output::execution_error(error_mode, message),
+                    };
+                }
+
+                paginator.into_items()
+            }
+            _ => res_val,
+        };
+
+        let contents = match (self.envelope, media_content) {
+            (true, media_content) => {
+                // Binary bodies are returned as a separate content item next to the envelope
+                let body = if media_content.is_some() {
+                    Value::Null
+                } else {
+                    res_val
+                };
+                let mut contents = vec![output::json_content(json!({
+                    "status": response_status,
+                    "headers": response_headers,
+                    "body": body,
+                }))?];
+                contents.extend(media_content);
+                contents
+            }
+            (false, Some(media_content)) => vec![media_content],
+            (false, None) => match res_val {
+                Value::String(text) if truncated => vec![Content::text(text)],
+                res_val => vec![output::json_content(res_val)?],
+            },
+        };
+
+        let result = CallToolResult::success(contents);
+
+        if let (Some(cache), Some(cache_key)) = (&self.cache, cache_key) {
+            let cache_control = first_page_headers
+                .get(CACHE_CONTROL)
+                .and_then(|value| value.to_str().ok());
+            if let Some(ttl) = cache.ttl_for(cache_control) {
+                cache.insert(cache_key, &result, ttl).await;
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::prelude::BASE64_STANDARD;
+    use rmcp::model::ResourceContents;
+    use std::io::{Read, Write};
+
+    const JSON: &[(&str, &str)] = &[("Content-Type", "application/json")];
+
+    /// Answers each connection to a local port with the next raw HTTP response, in order.
+    fn serve(responses: Vec<String>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 8192];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        url
+    }
+
+    /// Accepts one connection on a local port and never answers it.
+    fn hang() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            std::thread::sleep(std::time::Duration::from_secs(30));
+        });
+        url
+    }
+
+    fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+        let mut response = format!(
+            "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n",
+            status,
+            body.len()
+        );
+        for (name, value) in headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str("\r\n");
+        response.push_str(body);
+        response
+    }
+
+    fn http_tool(
+        url: String,
+        extra: Value,
+        error_mode: ErrorMode,
+        max_output_bytes: Option<u64>,
+        cache: Option<ResponseCache>,
+    ) -> HttpTool {
+        let mut http_metadata = json!({"url": url, "method": "GET", "input_schema": {}});
+        if let Value::Object(extra) = extra {
+            http_metadata.as_object_mut().unwrap().extend(extra);
+        }
+        HttpTool::new(
+            0,
+            serde_json::from_value(http_metadata).unwrap(),
+            error_mode,
+            max_output_bytes,
+            cache,
+            TemplateMode::SIMPLE,
+            None,
+        )
+    }
+
+    async fn call_http(
+        url: String,
+        extra: Value,
+        error_mode: ErrorMode,
+    ) -> Result<CallToolResult, ErrorData> {
+        let tool = http_tool(url, extra, error_mode, None, None);
+        tool.call(&json!({"input": {}}), CancellationToken::new())
+            .await
+    }
+
+    fn text(result: &CallToolResult) -> String {
+        let content = &result.content.as_ref().unwrap()[0];
+        content.as_text().unwrap().text.clone()
+    }
+
+    fn json_text(result: &CallToolResult) -> Value {
+        serde_json::from_str(&text(result)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn envelope_wraps_status_allowlisted_headers_and_body() {
+        let url = serve(vec![response(
+            "201 Created",
+            &[
+                ("Content-Type", "application/json"),
+                ("X-Request-Id", "abc"),
+                ("X-Other", "hidden"),
+            ],
+            r#"{"a": 1}"#,
+        )]);
+        let result = call_http(
+            url,
+            json!({"response_config": {"envelope": true, "header_allowlist": ["x-request-id"]}}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            json_text(&result),
+            json!({"status": 201, "headers": {"x-request-id": "abc"}, "body": {"a": 1}})
+        );
+    }
+
+    #[tokio::test]
+    async fn body_is_returned_as_is_without_envelope() {
+        let url = serve(vec![response("200 OK", JSON, r#"{"a": 1}"#)]);
+        let result = call_http(url, json!({}), ErrorMode::PROTOCOL)
+            .await
+            .unwrap();
+        assert_eq!(json_text(&result), json!({"a": 1}));
+    }
+
+    #[tokio::test]
+    async fn success_status_codes_accept_listed_non_2xx_statuses() {
+        let url = serve(vec![response("404 Not Found", JSON, r#"{"found": false}"#)]);
+        let result = call_http(
+            url,
+            json!({"response_config": {"success_status_codes": [404]}}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(json_text(&result), json!({"found": false}));
+
+        let url = serve(vec![response("404 Not Found", &[], "{}")]);
+        let err = call_http(url, json!({}), ErrorMode::PROTOCOL)
+            .await
+            .unwrap_err();
+        assert!(
+            err.message.contains("got status code : 404"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[tokio::test]
+    async fn result_error_mode_returns_failed_statuses_as_error_results() {
+        let url = serve(vec![response("503 Service Unavailable", &[], "down")]);
+        let result = call_http(url, json!({}), ErrorMode::RESULT).await.unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert!(
+            text(&result).contains("got status code : 503"),
+            "{}",
+            text(&result)
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_responses_are_returned_as_media_content() {
+        let url = serve(vec![
+            response("200 OK", &[("Content-Type", "image/png")], "png-bytes"),
+            response("200 OK", &[("Content-Type", "application/pdf")], "%PDF"),
+        ]);
+
+        let result = call_http(url.clone(), json!({}), ErrorMode::PROTOCOL)
+            .await
+            .unwrap();
+        let image = result.content.as_ref().unwrap()[0].as_image().unwrap();
+        assert_eq!(image.mime_type, "image/png");
+        assert_eq!(BASE64_STANDARD.decode(&image.data).unwrap(), b"png-bytes");
+
+        let result = call_http(url.clone(), json!({}), ErrorMode::PROTOCOL)
+            .await
+            .unwrap();
+        let resource = result.content.as_ref().unwrap()[0].as_resource().unwrap();
+        match &resource.resource {
+            ResourceContents::BlobResourceContents {
+                uri,
+                mime_type,
+                blob,
+            } => {
+                assert_eq!(uri, &url);
+                assert_eq!(mime_type.as_deref(), Some("application/pdf"));
+                assert_eq!(BASE64_STANDARD.decode(blob).unwrap(), b"%PDF");
+            }
+            other => panic!("unexpected resource {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn envelope_leaves_binary_body_null_and_appends_media_content() {
+        let url = serve(vec![response(
+            "200 OK",
+            &[("Content-Type", "image/png")],
+            "png-bytes",
+        )]);
+        let result = call_http(
+            url,
+            json!({"response_config": {"envelope": true}}),
+            ErrorMode::PROTOCOL,
+        )
+        .await
+        .unwrap();
+        let contents = result.content.as_ref().unwrap();
+        assert_eq!(contents.len(), 2);
+        assert_eq!(json_text(&result)["body"], Value::Null);
+        assert!(contents[1].as_image().is_some());
+    }
+
+    #[tokio::test]
+    async fn protocol_error_mode_keeps_failed_statuses_as_protocol_errors() {
+        let url = serve(vec![response("503 Service Unavailable", &[], "down")]);
+        assert!(
+            call_http(url, json!({}), ErrorMode::PROTOCOL)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_content_over_max_binary_bytes_is_rejected() {
+        let url = serve(vec![response(
+            "200 OK",
+            &[("Content-Type", "image/png")],
+            "png-bytes",
+        )]);
+        let err = call_http(url, json!({"max_binary_bytes": 4}), ErrorMode::PROTOCOL)
+            .await
+            .unwrap_err();
+        assert!(
+            err.message.contains("exceeding the limit of 4 bytes"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[tokio::test]
+    async fn cached_responses_are_served_without_a_request() {
+        // The server answers a single connection, so a second request would fail
+        let url = serve(vec![response("200 OK", JSON, r#"{"a": 1}"#)]);
+        let cache_config = serde_json::from_value(json!({"ttl": "1m"})).unwrap();
+        let cache = ResponseCache::new("tool".to_string(), cache_config);
+        let tool = http_tool(url, json!({}), ErrorMode::PROTOCOL, None, Some(cache));
+
+        for _ in 0..2 {
+            let result = tool
+                .call(&json!({"input": {}}), CancellationToken::new())
+                .await
+                .unwrap();
+            assert_eq!(json_text(&result), json!({"a": 1}));
+        }
+    }
+
+    #[tokio::test]
+    async fn max_output_bytes_truncates_the_body_with_a_marker() {
+        let url = serve(vec![response("200 OK", JSON, r#"{"items": [1, 2, 3]}"#)]);
+        let tool = http_tool(url, json!({}), ErrorMode::PROTOCOL, Some(8), None);
+        let result = tool
+            .call(&json!({"input": {}}), CancellationToken::new())
+            .await
+            .unwrap();
+        // A truncated JSON body can no longer be parsed, so it is returned as text
+        assert_eq!(
+            text(&result),
+            "{\"items\"\n[Output truncated to 8 bytes, original size 20 bytes]"
+        );
+    }
+
+    #[tokio::test]
+    async fn cancellation_aborts_the_request_in_flight() {
+        let tool = http_tool(hang(), json!({}), ErrorMode::PROTOCOL, None, None);
+        let ct = CancellationToken::new();
+        let cancel = ct.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+
+        let err = tool.call(&json!({"input": {}}), ct).await.unwrap_err();
+        assert!(err.message.contains("was cancelled"), "{}", err.message);
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected_naming_the_header() {
+        // SIMPLE templates escape control characters like JSON does, FULL ones output them as is
+        let full_tool = |headers: Value| {
+            let http_metadata = json!({
+                "url": "http://127.0.0.1:9",
+                "method": "GET",
+                "headers": headers,
+                "input_schema": {},
+            });
+            HttpTool::new(
+                0,
+                serde_json::from_value(http_metadata).unwrap(),
+                ErrorMode::PROTOCOL,
+                None,
+                None,
+                TemplateMode::FULL,
+                None,
+            )
+        };
+
+        let tool = full_tool(json!({"X-Name": "{{ input.name }}"}));
+        let err = tool
+            .render(&json!({"input": {"name": "a\r\nX-Admin: 1"}}))
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::INVALID_PARAMS);
+        assert!(
+            err.message.starts_with("Invalid value for header X-Name"),
+            "{}",
+            err.message
+        );
+
+        let tool = full_tool(json!({"Bad Name": "{{ input.name }}"}));
+        let err = tool.render(&json!({"input": {"name": "ok"}})).unwrap_err();
+        assert_eq!(err.code, ErrorCode::INVALID_PARAMS);
+        assert!(
+            err.message.starts_with("Invalid header name Bad Name"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[tokio::test]
+    async fn page_read_errors_fail_the_call() {
+        let url = serve(vec![
+            response(
+                "200 OK",
+                &[
+                    ("content-type", "application/json"),
+                    ("link", r#"</items?page=2>; rel="next""#),
+                ],
+                "[1, 2]",
+            ),
+            // The connection closes before the announced body length is reached
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 100\r\nconnection: close\r\n\r\n[3".to_string(),
+        ]);
+
+        let err = call_http(
+            format!("{}/items", url),
+            json!({"pagination": {"strategy": "LINK_HEADER"}}),
+            ErrorMode::RESULT,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.message
+                .starts_with(&format!("Error while reading page {}/items?page=2", url)),
+            "{}",
+            err.message
+        );
+    }
+}
@@ -0,0 +1,702 @@
+use crate::core::config::{BackgroundConfig, ErrorMode};
+use crate::core::output;
+use duration_string::DurationString;
+use futures_core::future::BoxFuture;
+use rmcp::ErrorData;
+use rmcp::model::{CallToolResult, ErrorCode};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// The companion tools generated for a background tool.
+#[derive(Debug, Clone, Copy)]
+pub enum JobAction {
+    Status,
+    Result,
+    Cancel,
+}
+
+impl JobAction {
+    pub const ALL: [JobAction; 3] = [JobAction::Status, JobAction::Result, JobAction::Cancel];
+
+    pub fn tool_name(&self, tool_name: &str) -> String {
+        match self {
+            JobAction::Status => format!("{}_status", tool_name),
+            JobAction::Result => format!("{}_result", tool_name),
+            JobAction::Cancel => format!("{}_cancel", tool_name),
+        }
+    }
+
+    pub fn description(&self, tool_name: &str) -> String {
+        match self {
+            JobAction::Status => format!(
+                "Returns the status of a background job started by {}: running, succeeded, failed or cancelled",
+                tool_name
+            ),
+            JobAction::Result => format!(
+                "Returns the result of a finished background job started by {}",
+                tool_name
+            ),
+            JobAction::Cancel => {
+                format!("Cancels a running background job started by {}", tool_name)
+            }
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct JobRecord {
+    job_id: String,
+    status: JobStatus,
+    started_at_ms: u64,
+    finished_at_ms: Option<u64>,
+    result: Option<CallToolResult>,
+    error: Option<String>,
+}
+
+struct Job {
+    record: JobRecord,
+    ct: CancellationToken,
+}
+
+/// Table of the background jobs of one tool, optionally persisted as JSON files in a directory.
+pub struct JobStore {
+    tool_name: String,
+    error_mode: ErrorMode,
+    retention: Duration,
+    max_jobs: usize,
+    disk_path: Option<PathBuf>,
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl JobStore {
+    const DEFAULT_RETENTION: Duration = Duration::from_secs(60 * 60);
+    const DEFAULT_MAX_JOBS: usize = 100;
+
+    pub fn new(tool_name: String, error_mode: ErrorMode, config: BackgroundConfig) -> Self {
+        let retention = config
+            .retention
+            .map(|retention| {
+                DurationString::from_string(retention.clone())
+                    .unwrap_or_else(|err| {
+                        panic!(
+                            "Invalid background retention {} for tool {}: {}",
+                            retention, tool_name, err
+                        )
+                    })
+                    .into()
+            })
+            .unwrap_or(Self::DEFAULT_RETENTION);
+
+        let disk_path = config.disk_path.map(PathBuf::from);
+        let jobs = disk_path
+            .as_ref()
+            .map(|dir| Self::load(&tool_name, dir))
+            .unwrap_or_default();
+
+        Self {
+            tool_name,
+            error_mode,
+            retention,
+            max_jobs: config.max_jobs.unwrap_or(Self::DEFAULT_MAX_JOBS).max(1),
+            disk_path,
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0)
+    }
+
+    /// Reads persisted jobs. Jobs that were running when the server stopped are marked as failed.
+    fn load(tool_name: &str, dir: &Path) -> HashMap<String, Job> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return HashMap::new();
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                let job_id = Self::disk_entry_job_id(&path)?;
+                let bytes = std::fs::read(&path).ok()?;
+                serde_json::from_slice::<JobRecord>(&bytes)
+                    .ok()
+                    .filter(|record| record.job_id == job_id)
+            })
+            .map(|mut record| {
+                if record.status == JobStatus::Running {
+                    tracing::warn!(tool = %tool_name, job_id = %record.job_id, "job was interrupted by a restart");
+                    record.status = JobStatus::Failed;
+                    record.finished_at_ms = Some(Self::now());
+                    record.error = Some("The job was interrupted by a server restart".to_string());
+                }
+                let job = Job {
+                    record,
+                    ct: CancellationToken::new(),
+                };
+                (job.record.job_id.clone(), job)
+            })
+            .collect()
+    }
+
+    fn new_job_id() -> String {
+        uuid::Uuid::new_v4().simple().to_string()
+    }
+
+    /// Returns the job id of a file named like the ones written by `persist`, so other files
+    /// sharing the directory are left alone.
+    fn disk_entry_job_id(path: &Path) -> Option<&str> {
+        if path.extension()? != "json" {
+            return None;
+        }
+        path.file_stem()?.to_str().filter(|stem| {
+            uuid::Uuid::try_parse(stem).is_ok_and(|uuid| uuid.simple().to_string() == *stem)
+        })
+    }
+
+    fn disk_entry_path(&self, job_id: &str) -> Option<PathBuf> {
+        self.disk_path
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", job_id)))
+    }
+
+    async fn persist(&self, record: &JobRecord) {
+        let Some(path) = self.disk_entry_path(&record.job_id) else {
+            return;
+        };
+
+        let written = match serde_json::to_vec(record) {
+            Ok(bytes) => match path.parent() {
+                Some(dir) => match tokio::fs::create_dir_all(dir).await {
+                    Ok(()) => tokio::fs::write(&path, bytes).await,
+                    Err(err) => Err(err),
+                },
+                None => tokio::fs::write(&path, bytes).await,
+            },
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = written {
+            tracing::warn!(tool = %self.tool_name, error = %err, "failed to write job to disk");
+        }
+    }
+
+    /// Drops finished jobs past their retention. With `make_room`, the oldest finished jobs
+    /// are dropped as well while the table is full.
+    ///
+    /// Returns the ids of the removed jobs.
+    fn prune(&self, jobs: &mut HashMap<String, Job>, make_room: bool) -> Vec<String> {
+        let now = Self::now();
+        let retention = self.retention.as_millis() as u64;
+
+        let mut removed: Vec<String> = jobs
+            .values()
+            .filter(|job| {
+                job.record
+                    .finished_at_ms
+                    .is_some_and(|finished_at| finished_at + retention <= now)
+            })
+            .map(|job| job.record.job_id.clone())
+            .collect();
+        for job_id in removed.iter() {
+            jobs.remove(job_id);
+        }
+
+        while make_room && jobs.len() >= self.max_jobs {
+            let oldest_finished = jobs
+                .values()
+                .filter(|job| job.record.status != JobStatus::Running)
+                .min_by_key(|job| job.record.finished_at_ms)
+                .map(|job| job.record.job_id.clone());
+            let Some(job_id) = oldest_finished else {
+                break;
+            };
+            jobs.remove(&job_id);
+            removed.push(job_id);
+        }
+
+        removed
+    }
+
+    async fn remove_persisted(&self, job_ids: Vec<String>) {
+        for job_id in job_ids {
+            if let Some(path) = self.disk_entry_path(&job_id) {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+        }
+    }
+
+    fn status_value(record: &JobRecord) -> Value {
+        json!({
+            "job_id": record.job_id,
+            "status": record.status,
+            "started_at_ms": record.started_at_ms,
+            "finished_at_ms": record.finished_at_ms,
+            "elapsed_ms": record.finished_at_ms.unwrap_or_else(Self::now) - record.started_at_ms,
+        })
+    }
+
+    /// Starts `run` in the background and returns the status of the new job.
+    ///
+    /// `run` receives a token that is cancelled by the cancel tool.
+    pub async fn start<F>(self: &Arc<Self>, run: F) -> Result<CallToolResult, ErrorData>
+    where
+        F: FnOnce(CancellationToken) -> BoxFuture<'static, Result<CallToolResult, ErrorData>>,
+    {
+        let ct = CancellationToken::new();
+        let record = JobRecord {
+            job_id: Self::new_job_id(),
+            status: JobStatus::Running,
+            started_at_ms: Self::now(),
+            finished_at_ms: None,
+            result: None,
+            error: None,
+        };
+
+        let (removed, full) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let removed = self.prune(&mut jobs, true);
+            let full = jobs.len() >= self.max_jobs;
+            if !full {
+                jobs.insert(
+                    record.job_id.clone(),
+                    Job {
+                        record: record.clone(),
+                        ct: ct.clone(),
+                    },
+                );
+            }
+            (removed, full)
+        };
+        self.remove_persisted(removed).await;
+
+        if full {
+            return output::execution_error(
+                &self.error_mode,
+                format!(
+                    "Too many running jobs for {}, the limit is {}",
+                    self.tool_name, self.max_jobs
+                ),
+            );
+        }
+        self.persist(&record).await;
+
+        tracing::info!(tool = %self.tool_name, job_id = %record.job_id, "job started");
+
+        let job = run(ct);
+        let store = self.clone();
+        let job_id = record.job_id.clone();
+        tokio::spawn(async move {
+            let result = job.await;
+            store.finish(&job_id, result).await;
+        });
+
+        Ok(CallToolResult::success(vec![output::json_content(
+            Self::status_value(&record),
+        )?]))
+    }
+
+    async fn finish(&self, job_id: &str, result: Result<CallToolResult, ErrorData>) {
+        let record = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let Some(job) = jobs.get_mut(job_id) else {
+                return;
+            };
+
+            // A cancelled job ends once its run returned, whatever the interrupted tool returned
+            if job.record.status == JobStatus::Running {
+                match result {
+                    _ if job.ct.is_cancelled() => job.record.status = JobStatus::Cancelled,
+                    Ok(result) => {
+                        job.record.status = if result.is_error == Some(true) {
+                            JobStatus::Failed
+                        } else {
+                            JobStatus::Succeeded
+                        };
+                        job.record.result = Some(result);
+                    }
+                    Err(err) => {
+                        job.record.status = JobStatus::Failed;
+                        job.record.error = Some(err.message.to_string());
+                    }
+                }
+                job.record.finished_at_ms = Some(Self::now());
+            }
+            job.record.clone()
+        };
+
+        tracing::info!(tool = %self.tool_name, job_id, status = ?record.status, "job finished");
+        self.persist(&record).await;
+    }
+
+    fn job_id(arguments: &Value) -> Result<&str, ErrorData> {
+        arguments
+            .get("job_id")
+            .and_then(Value::as_str)
+            .ok_or_else(|| {
+                ErrorData::new(
+                    ErrorCode::INVALID_PARAMS,
+                    "job_id is required".to_string(),
+                    None,
+                )
+            })
+    }
+
+    fn unknown_job(job_id: &str) -> ErrorData {
+        ErrorData::new(
+            ErrorCode::INVALID_PARAMS,
+            format!("Unknown or expired job id {}", job_id),
+            None,
+        )
+    }
+
+    /// Handles a call to one of the companion tools.
+    pub async fn call(
+        &self,
+        action: JobAction,
+        arguments: &Value,
+    ) -> Result<CallToolResult, ErrorData> {
+        let job_id = Self::job_id(arguments)?;
+
+        let (removed, job) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let removed = self.prune(&mut jobs, false);
+
+            // The job stays running until its run returned, which for a command is once
+            // its process is reaped
+            let job = jobs.get_mut(job_id).map(|job| {
+                let cancelled = matches!(action, JobAction::Cancel)
+                    && job.record.status == JobStatus::Running
+                    && !job.ct.is_cancelled();
+                if cancelled {
+                    job.ct.cancel();
+                }
+                (job.record.clone(), cancelled)
+            });
+            (removed, job)
+        };
+        self.remove_persisted(removed).await;
+
+        let Some((record, cancelled)) = job else {
+            return Err(Self::unknown_job(job_id));
+        };
+
+        if cancelled {
+            tracing::info!(tool = %self.tool_name, job_id, "job cancellation requested");
+        }
+
+        match (action, record.status) {
+            (JobAction::Status | JobAction::Cancel, _)
+            | (JobAction::Result, JobStatus::Running) => {
+                Ok(CallToolResult::success(vec![output::json_content(
+                    Self::status_value(&record),
+                )?]))
+            }
+            (JobAction::Result, JobStatus::Cancelled) => output::execution_error(
+                &self.error_mode,
+                format!("Job {} was cancelled", record.job_id),
+            ),
+            (JobAction::Result, JobStatus::Succeeded | JobStatus::Failed) => {
+                match (record.result, record.error) {
+                    (Some(result), _) => Ok(result),
+                    (None, error) => output::execution_error(
+                        &self.error_mode,
+                        error.unwrap_or_else(|| format!("Job {} failed", record.job_id)),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::Content;
+
+    fn store(config: Value) -> Arc<JobStore> {
+        Arc::new(JobStore::new(
+            "tool".to_string(),
+            ErrorMode::RESULT,
+            serde_json::from_value(config).unwrap(),
+        ))
+    }
+
+    fn status(result: &CallToolResult) -> Value {
+        let content = &result.content.as_ref().unwrap()[0];
+        serde_json::from_str(&content.as_text().unwrap().text).unwrap()
+    }
+
+    async fn start(store: &Arc<JobStore>, result: Result<CallToolResult, ErrorData>) -> String {
+        let started = store
+            .start(move |_| Box::pin(async move { result }))
+            .await
+            .unwrap();
+        status(&started)["job_id"].as_str().unwrap().to_string()
+    }
+
+    /// Starts a job that runs until it is cancelled.
+    async fn start_pending(store: &Arc<JobStore>) -> String {
+        let started = store
+            .start(|ct| {
+                Box::pin(async move {
+                    ct.cancelled().await;
+                    Ok(CallToolResult::success(vec![]))
+                })
+            })
+            .await
+            .unwrap();
+        status(&started)["job_id"].as_str().unwrap().to_string()
+    }
+
+    async fn wait_until_finished(store: &JobStore, job_id: &str) -> Value {
+        for _ in 0..100 {
+            let result = store
+                .call(JobAction::Status, &json!({ "job_id": job_id }))
+                .await
+                .unwrap();
+            let status = status(&result);
+            if status["status"] != json!("running") {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job {} did not finish", job_id);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("easymcp-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn finished_jobs_report_their_result() {
+        let store = store(json!({}));
+        let job_id = start(
+            &store,
+            Ok(CallToolResult::success(vec![Content::text("done")])),
+        )
+        .await;
+
+        let status = wait_until_finished(&store, &job_id).await;
+        assert_eq!(status["status"], json!("succeeded"));
+        assert!(status["finished_at_ms"].is_u64());
+
+        let result = store
+            .call(JobAction::Result, &json!({ "job_id": job_id }))
+            .await
+            .unwrap();
+        assert_eq!(result.content, Some(vec![Content::text("done")]));
+
+        let job_id = start(&store, Err(ErrorData::internal_error("boom", None))).await;
+        assert_eq!(
+            wait_until_finished(&store, &job_id).await["status"],
+            json!("failed")
+        );
+        let result = store
+            .call(JobAction::Result, &json!({ "job_id": job_id }))
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+    }
+
+    #[tokio::test]
+    async fn cancel_stops_a_running_job() {
+        let store = store(json!({}));
+        let job_id = start_pending(&store).await;
+
+        let result = store
+            .call(JobAction::Result, &json!({ "job_id": job_id }))
+            .await
+            .unwrap();
+        assert_eq!(status(&result)["status"], json!("running"));
+
+        store
+            .call(JobAction::Cancel, &json!({ "job_id": job_id }))
+            .await
+            .unwrap();
+        assert_eq!(
+            wait_until_finished(&store, &job_id).await["status"],
+            json!("cancelled")
+        );
+        let result = store
+            .call(JobAction::Result, &json!({ "job_id": job_id }))
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_keep_running_until_their_run_returns() {
+        let store = store(json!({}));
+        let reaped = Arc::new(tokio::sync::Notify::new());
+        let job_reaped = reaped.clone();
+        let started = store
+            .start(move |ct| {
+                Box::pin(async move {
+                    ct.cancelled().await;
+                    job_reaped.notified().await;
+                    Ok(CallToolResult::success(vec![]))
+                })
+            })
+            .await
+            .unwrap();
+        let job_id = status(&started)["job_id"].as_str().unwrap().to_string();
+
+        let result = store
+            .call(JobAction::Cancel, &json!({ "job_id": job_id }))
+            .await
+            .unwrap();
+        assert_eq!(status(&result)["status"], json!("running"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let result = store
+            .call(JobAction::Status, &json!({ "job_id": job_id }))
+            .await
+            .unwrap();
+        assert_eq!(status(&result)["status"], json!("running"));
+        assert_eq!(status(&result)["finished_at_ms"], Value::Null);
+
+        reaped.notify_one();
+        let status = wait_until_finished(&store, &job_id).await;
+        assert_eq!(status["status"], json!("cancelled"));
+        assert!(status["finished_at_ms"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn unknown_job_ids_are_rejected() {
+        let store = store(json!({}));
+        let err = store
+            .call(JobAction::Status, &json!({ "job_id": "missing" }))
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("Unknown or expired job id"));
+        assert!(store.call(JobAction::Status, &json!({})).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn expired_jobs_are_pruned() {
+        let store = store(json!({"retention": "1ms"}));
+        let job_id = start(&store, Ok(CallToolResult::success(vec![]))).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(
+            store
+                .call(JobAction::Status, &json!({ "job_id": job_id }))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn max_jobs_drops_finished_jobs_and_rejects_new_ones_when_all_run() {
+        let store = store(json!({"max_jobs": 2}));
+        let finished = start(&store, Ok(CallToolResult::success(vec![]))).await;
+        wait_until_finished(&store, &finished).await;
+        let running = start_pending(&store).await;
+
+        // Starting a third job drops the finished one
+        let another = start_pending(&store).await;
+        assert!(
+            store
+                .call(JobAction::Status, &json!({ "job_id": finished }))
+                .await
+                .is_err()
+        );
+
+        let result = store
+            .start(|_| Box::pin(async { Ok(CallToolResult::success(vec![])) }))
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+
+        for job_id in [running, another] {
+            store
+                .call(JobAction::Cancel, &json!({ "job_id": job_id }))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn persisted_jobs_are_reloaded_and_interrupted_ones_fail() {
+        let dir = temp_dir("jobs-persist");
+        let config = json!({"disk_path": dir});
+        let store = store(config.clone());
+        let finished = start(
+            &store,
+            Ok(CallToolResult::success(vec![Content::text("done")])),
+        )
+        .await;
+        wait_until_finished(&store, &finished).await;
+        let interrupted = start_pending(&store).await;
+
+        let reloaded = JobStore::new(
+            "tool".to_string(),
+            ErrorMode::RESULT,
+            serde_json::from_value(config).unwrap(),
+        );
+        assert_eq!(
+            wait_until_finished(&reloaded, &finished).await["status"],
+            json!("succeeded")
+        );
+        assert_eq!(
+            wait_until_finished(&reloaded, &interrupted).await["status"],
+            json!("failed")
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn only_job_files_are_loaded_from_the_directory() {
+        let dir = temp_dir("jobs-foreign-files");
+        let config = json!({"disk_path": dir});
+        let store = store(config.clone());
+        let job_id = start(
+            &store,
+            Ok(CallToolResult::success(vec![Content::text("done")])),
+        )
+        .await;
+        wait_until_finished(&store, &job_id).await;
+
+        // Files with the same content but another name or extension are not jobs of this store
+        let record = std::fs::read(dir.join(format!("{}.json", job_id))).unwrap();
+        let other_id = JobStore::new_job_id();
+        let foreign = String::from_utf8(record)
+            .unwrap()
+            .replace(&job_id, &other_id);
+        std::fs::write(dir.join(format!("{}.json.bak", other_id)), &foreign).unwrap();
+        std::fs::write(dir.join(format!("cache-{}.json", other_id)), &foreign).unwrap();
+        std::fs::write(dir.join("notes.json"), &foreign).unwrap();
+        std::fs::write(
+            dir.join(format!("{}.json", JobStore::new_job_id())),
+            &foreign,
+        )
+        .unwrap();
+
+        let reloaded = JobStore::new(
+            "tool".to_string(),
+            ErrorMode::RESULT,
+            serde_json::from_value(config).unwrap(),
+        );
+        let jobs = reloaded.jobs.lock().unwrap();
+        assert_eq!(jobs.keys().collect::<Vec<_>>(), vec![&job_id]);
+        drop(jobs);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,418 @@
+use crate::core::config::{ErrorMode, LimitsConfig, RateLimitConfig};
+use crate::core::output;
+use duration_string::DurationString;
+use rmcp::ErrorData;
+use rmcp::model::{CallToolResult, ErrorCode};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+/// Slots a call holds in its limiters, added to the request extensions.
+///
+/// A background call hands them to its job, so the job counts towards `max_concurrency`
+/// until it finishes. The slots are freed once every clone is dropped.
+#[derive(Clone)]
+pub struct Permits {
+    _slots: Arc<Vec<OwnedSemaphorePermit>>,
+}
+
+/// Token bucket holding up to `requests` tokens, refilled continuously over `interval`.
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(requests: u32, interval: Duration) -> Self {
+        let capacity = requests as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_second: capacity / interval.as_secs_f64(),
+            updated_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token, or returns how long to wait until one is available.
+    fn take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.refill_per_second,
+        ))
+    }
+
+    /// Gives back a token taken by a call that was not let through after all.
+    fn refund(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(self.capacity);
+    }
+}
+
+/// Tokens taken by a call being admitted, given back when it is dropped before the call is let
+/// through: on a later rejection, a queue timeout, or cancellation.
+struct TakenTokens<'l> {
+    limiters: Vec<&'l Limiter>,
+}
+
+impl TakenTokens<'_> {
+    fn keep(mut self) {
+        self.limiters.clear();
+    }
+}
+
+impl Drop for TakenTokens<'_> {
+    fn drop(&mut self) {
+        for limiter in self.limiters.iter() {
+            limiter.refund_token();
+        }
+    }
+}
+
+/// Why a call was not let through.
+pub enum Rejection {
+    Limited {
+        error_mode: ErrorMode,
+        message: String,
+    },
+    Cancelled,
+}
+
+impl Rejection {
+    pub fn into_result(self) -> Result<CallToolResult, ErrorData> {
+        match self {
+            Rejection::Limited {
+                error_mode,
+                message,
+            } => output::execution_error(&error_mode, message),
+            Rejection::Cancelled => Err(ErrorData::new(
+                ErrorCode::INTERNAL_ERROR,
+                "Call was cancelled while waiting for a free slot".to_string(),
+                None,
+            )),
+        }
+    }
+}
+
+/// Concurrency and rate limits of a tool, a session or the whole server.
+pub struct Limiter {
+    scope: String,
+    error_mode: ErrorMode,
+    semaphore: Option<(Arc<Semaphore>, usize)>,
+    queue_timeout: Option<Duration>,
+    rate_limit: Option<(Mutex<TokenBucket>, RateLimitConfig)>,
+}
+
+impl Limiter {
+    /// Returns `None` when no limit is configured.
+    ///
+    /// `scope` names what is limited in messages, e.g. "tool search" or "the server".
+    pub fn new(scope: String, error_mode: ErrorMode, config: &LimitsConfig) -> Option<Self> {
+        if config.max_concurrency.is_none() && config.rate_limit.is_none() {
+            return None;
+        }
+
+        let semaphore = config.max_concurrency.map(|max_concurrency| {
+            if max_concurrency == 0 {
+                panic!("Invalid max_concurrency for {}: must be at least 1", scope);
+            }
+            (Arc::new(Semaphore::new(max_concurrency)), max_concurrency)
+        });
+
+        let queue_timeout = config.queue_timeout.as_ref().map(|queue_timeout| {
+            DurationString::from_string(queue_timeout.clone())
+                .unwrap_or_else(|err| panic!("Invalid queue_timeout for {}: {}", scope, err))
+                .into()
+        });
+
+        let rate_limit = config.rate_limit.as_ref().map(|rate_limit| {
+            let interval: Duration = DurationString::from_string(rate_limit.interval.clone())
+                .unwrap_or_else(|err| panic!("Invalid rate_limit interval for {}: {}", scope, err))
+                .into();
+            if rate_limit.requests == 0 || interval.is_zero() {
+                panic!(
+                    "Invalid rate_limit for {}: requests and interval must be greater than zero",
+                    scope
+                );
+            }
+            (
+                Mutex::new(TokenBucket::new(rate_limit.requests, interval)),
+                rate_limit.clone(),
+            )
+        });
+
+        Some(Self {
+            scope,
+            error_mode,
+            semaphore,
+            queue_timeout,
+            rate_limit,
+        })
+    }
+
+    fn limited(&self, message: String) -> Rejection {
+        tracing::warn!(scope = %self.scope, "{}", message);
+        Rejection::Limited {
+            error_mode: self.error_mode.clone(),
+            message,
+        }
+    }
+
+    fn take_token(&self) -> Result<(), Rejection> {
+        let Some((ref bucket, ref config)) = self.rate_limit else {
+            return Ok(());
+        };
+
+        bucket.lock().unwrap().take().map_err(|retry_after| {
+            self.limited(format!(
+                "Rate limited: {} allows {} calls per {}, retry after {} s",
+                self.scope,
+                config.requests,
+                config.interval,
+                retry_after.as_secs_f64().ceil().max(1.0) as u64
+            ))
+        })
+    }
+
+    fn refund_token(&self) {
+        if let Some((ref bucket, _)) = self.rate_limit {
+            bucket.lock().unwrap().refund();
+        }
+    }
+
+    async fn acquire(
+        &self,
+        ct: &CancellationToken,
+    ) -> Result<Option<OwnedSemaphorePermit>, Rejection> {
+        let Some((ref semaphore, max_concurrency)) = self.semaphore else {
+            return Ok(None);
+        };
+
+        let permit = semaphore.clone().acquire_owned();
+        let permit = async {
+            match self.queue_timeout {
+                Some(queue_timeout) => tokio::time::timeout(queue_timeout, permit)
+                    .await
+                    .map_err(|_| {
+                        self.limited(format!(
+                            "Timed out after {} waiting for a free slot: {} allows {} concurrent calls",
+                            DurationString::from(queue_timeout),
+                            self.scope,
+                            max_concurrency
+                        ))
+                    }),
+                None => Ok(permit.await),
+            }
+        };
+
+        tokio::select! {
+            permit = permit => match permit? {
+                Ok(permit) => Ok(Some(permit)),
+                // The semaphore is never closed
+                Err(_) => Err(Rejection::Cancelled),
+            },
+            _ = ct.cancelled() => Err(Rejection::Cancelled),
+        }
+    }
+}
+
+/// Checks every rate limit, then waits for a free slot in every limiter, in order.
+///
+/// A call only uses up rate limit tokens when it is let through: the tokens it took are given
+/// back if a later limiter rejects it, or it times out or is cancelled while queued.
+/// The returned permits must be held for the duration of the call.
+pub async fn admit(limiters: &[&Limiter], ct: &CancellationToken) -> Result<Permits, Rejection> {
+    let mut taken = TakenTokens { limiters: vec![] };
+    for limiter in limiters {
+        limiter.take_token()?;
+        taken.limiters.push(limiter);
+    }
+
+    let mut permits = Vec::new();
+    for limiter in limiters {
+        permits.extend(limiter.acquire(ct).await?);
+    }
+    taken.keep();
+    Ok(Permits {
+        _slots: Arc::new(permits),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(config: LimitsConfig) -> Limiter {
+        Limiter::new("tool test".to_string(), ErrorMode::RESULT, &config).unwrap()
+    }
+
+    fn rejection_message(rejection: Rejection) -> String {
+        match rejection {
+            Rejection::Limited { message, .. } => message,
+            Rejection::Cancelled => panic!("expected a limited rejection"),
+        }
+    }
+
+    #[test]
+    fn token_bucket_allows_a_burst_then_asks_to_wait() {
+        let mut bucket = TokenBucket::new(2, Duration::from_secs(1));
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_ok());
+
+        let retry_after = bucket.take().unwrap_err();
+        assert!(retry_after > Duration::from_millis(450));
+        assert!(retry_after <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn token_bucket_refills_over_the_interval_up_to_its_capacity() {
+        let mut bucket = TokenBucket::new(2, Duration::from_secs(1));
+        bucket.tokens = 0.0;
+        bucket.updated_at = Instant::now() - Duration::from_millis(750);
+        assert!(bucket.take().is_ok());
+        let retry_after = bucket.take().unwrap_err();
+        assert!(retry_after > Duration::from_millis(200));
+        assert!(retry_after <= Duration::from_millis(250));
+
+        bucket.updated_at = Instant::now() - Duration::from_secs(60);
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_err());
+    }
+
+    #[test]
+    fn rate_limit_reports_the_retry_after_in_whole_seconds() {
+        let limiter = limiter(LimitsConfig {
+            rate_limit: Some(RateLimitConfig {
+                requests: 30,
+                interval: "1m".to_string(),
+            }),
+            ..Default::default()
+        });
+        for _ in 0..30 {
+            assert!(limiter.take_token().is_ok());
+        }
+        assert_eq!(
+            rejection_message(limiter.take_token().unwrap_err()),
+            "Rate limited: tool test allows 30 calls per 1m, retry after 2 s"
+        );
+    }
+
+    #[test]
+    fn rate_limit_retry_after_is_at_least_one_second() {
+        let limiter = limiter(LimitsConfig {
+            rate_limit: Some(RateLimitConfig {
+                requests: 10,
+                interval: "1s".to_string(),
+            }),
+            ..Default::default()
+        });
+        for _ in 0..10 {
+            assert!(limiter.take_token().is_ok());
+        }
+        assert!(rejection_message(limiter.take_token().unwrap_err()).ends_with("retry after 1 s"));
+    }
+
+    #[tokio::test]
+    async fn concurrency_limit_queues_until_a_slot_is_free() {
+        let limiter = limiter(LimitsConfig {
+            max_concurrency: Some(1),
+            queue_timeout: Some("50ms".to_string()),
+            ..Default::default()
+        });
+        let ct = CancellationToken::new();
+
+        let permits = admit(&[&limiter], &ct).await.ok().unwrap();
+        let rejection = admit(&[&limiter], &ct).await.err().unwrap();
+        assert_eq!(
+            rejection_message(rejection),
+            "Timed out after 50ms waiting for a free slot: tool test allows 1 concurrent calls"
+        );
+
+        drop(permits);
+        assert!(admit(&[&limiter], &ct).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn cancelled_calls_stop_waiting_for_a_slot() {
+        let limiter = limiter(LimitsConfig {
+            max_concurrency: Some(1),
+            ..Default::default()
+        });
+        let ct = CancellationToken::new();
+        let _permits = admit(&[&limiter], &ct).await.ok().unwrap();
+
+        ct.cancel();
+        assert!(matches!(
+            admit(&[&limiter], &ct).await,
+            Err(Rejection::Cancelled)
+        ));
+    }
+
+    fn rate_limit(requests: u32) -> Option<RateLimitConfig> {
+        Some(RateLimitConfig {
+            requests,
+            interval: "1h".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn rejected_calls_give_back_their_tokens() {
+        let tool = limiter(LimitsConfig {
+            rate_limit: rate_limit(2),
+            ..Default::default()
+        });
+        let server = limiter(LimitsConfig {
+            rate_limit: rate_limit(1),
+            ..Default::default()
+        });
+        let ct = CancellationToken::new();
+
+        assert!(admit(&[&tool, &server], &ct).await.is_ok());
+        // The server limit rejects the call after the tool limit let it through
+        assert!(admit(&[&tool, &server], &ct).await.is_err());
+        assert!(admit(&[&tool, &server], &ct).await.is_err());
+
+        // Both rejected calls gave their tool token back
+        assert!(admit(&[&tool], &ct).await.is_ok());
+        assert!(admit(&[&tool], &ct).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn queued_calls_give_back_their_tokens_on_timeout_and_cancellation() {
+        let tool = limiter(LimitsConfig {
+            rate_limit: rate_limit(2),
+            ..Default::default()
+        });
+        let server = limiter(LimitsConfig {
+            max_concurrency: Some(1),
+            queue_timeout: Some("20ms".to_string()),
+            ..Default::default()
+        });
+        let ct = CancellationToken::new();
+        let _permits = admit(&[&tool, &server], &ct).await.ok().unwrap();
+
+        let rejection = admit(&[&tool, &server], &ct).await.err().unwrap();
+        assert!(rejection_message(rejection).starts_with("Timed out"));
+
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        assert!(matches!(
+            admit(&[&tool, &server], &cancelled).await,
+            Err(Rejection::Cancelled)
+        ));
+
+        // One token was used by the admitted call, the other is still there
+        assert!(tool.take_token().is_ok());
+        assert!(tool.take_token().is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::core::config::MockResponse;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::response::Response;
+use reqwest::Url;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Responses left to serve and the requests that came once they ran out.
+#[derive(Default)]
+struct MockState {
+    responses: VecDeque<MockResponse>,
+    unexpected: Vec<String>,
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+/// Local HTTP server answering requests with configured responses, in order.
+pub struct MockServer {
+    address: SocketAddr,
+    state: SharedState,
+    ct: CancellationToken,
+}
+
+impl MockServer {
+    pub async fn start() -> std::io::Result<Self> {
+        let state = SharedState::default();
+        let router = Router::new()
+            .fallback(Self::respond)
+            .with_state(state.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+
+        let ct = CancellationToken::new();
+        let shutdown = ct.clone();
+        tokio::spawn(async move {
+            let server = axum::serve(listener, router)
+                .with_graceful_shutdown(async move { shutdown.cancelled().await });
+            if let Err(err) = server.await {
+                tracing::error!(error = %err, "mock server shutdown with error");
+            }
+        });
+
+        Ok(Self { address, state, ct })
+    }
+
+    async fn respond(State(state): State<SharedState>, request: Request) -> Response {
+        let mut state = state.lock().unwrap();
+        let Some(response) = state.responses.pop_front() else {
+            state
+                .unexpected
+                .push(format!("{} {}", request.method(), request.uri()));
+            return Response::builder()
+                .status(StatusCode::NOT_IMPLEMENTED)
+                .body(Body::from("Unexpected request to the mock server"))
+                .unwrap_or_default();
+        };
+
+        let status = response
+            .status
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::OK);
+        let mut builder = Response::builder().status(status);
+        for (name, value) in response.headers.iter().flatten() {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::from_str(name), HeaderValue::from_str(value))
+            {
+                builder = builder.header(name, value);
+            }
+        }
+
+        builder
+            .body(Body::from(response.body.unwrap_or_default()))
+            .unwrap_or_default()
+    }
+
+    /// Sets the responses to the next requests, one each. Requests past the last one are unexpected.
+    pub fn respond_with(&self, responses: Vec<MockResponse>) {
+        let mut state = self.state.lock().unwrap();
+        state.responses = responses.into();
+        state.unexpected.clear();
+    }
+
+    /// Returns the requests that came after the responses ran out, as method and path.
+    pub fn unexpected_requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().unexpected.clone()
+    }
+
+    /// URL of the server, for tools to send their requests to.
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}", self.address)).expect("socket address is a valid host")
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.ct.cancel();
+    }
+}
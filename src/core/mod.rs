@@ -1,4 +1,20 @@
+mod cache;
+mod cassette;
 mod closure;
+mod command;
 pub mod config;
+mod context;
 pub mod engine;
+mod http;
+mod jobs;
+mod limit;
+mod mock;
+pub mod openapi;
+mod output;
+mod pagination;
+mod process;
+mod progress;
+mod sandbox;
+mod static_tool;
 mod template;
+pub mod testing;
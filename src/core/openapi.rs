@@ -0,0 +1,724 @@
+use crate::core::config::OpenApiSource;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashSet;
+
+const METHODS: [(&str, &str); 4] = [
+    ("get", "GET"),
+    ("put", "PUT"),
+    ("post", "POST"),
+    ("delete", "DELETE"),
+];
+const UNSUPPORTED_METHODS: [&str; 4] = ["patch", "head", "options", "trace"];
+// OpenAPI describes these with other fields, and HTTP tools set Content-Type themselves
+const IGNORED_HEADERS: [&str; 3] = ["accept", "authorization", "content-type"];
+
+/// Reads an OpenAPI 3 specification, in YAML or JSON, and returns a tool entry per operation
+/// in the format of the config file, with a warning for each skipped operation.
+pub async fn import(source: &OpenApiSource) -> Result<(Vec<Value>, Vec<String>), String> {
+    let text = tokio::fs::read_to_string(&source.path)
+        .await
+        .map_err(|err| err.to_string())?;
+    import_text(&text, source)
+}
+
+fn import_text(text: &str, source: &OpenApiSource) -> Result<(Vec<Value>, Vec<String>), String> {
+    let spec: Value = serde_yaml::from_str(text).map_err(|err| err.to_string())?;
+
+    let version = spec.get("openapi").and_then(Value::as_str).unwrap_or("");
+    if !version.starts_with("3.") {
+        return Err("only OpenAPI 3 specifications are supported".to_string());
+    }
+
+    let mut importer = Importer {
+        spec: &spec,
+        source,
+        base_url: base_url(&spec, source)?,
+        names: HashSet::new(),
+        warnings: Vec::new(),
+    };
+    let tools = importer.import()?;
+    Ok((tools, importer.warnings))
+}
+
+/// URL of the first server with its variables set to their defaults, unless `base_url` is set.
+fn base_url(spec: &Value, source: &OpenApiSource) -> Result<String, String> {
+    if let Some(ref base_url) = source.base_url {
+        return Ok(base_url.trim_end_matches('/').to_string());
+    }
+
+    let server = spec
+        .get("servers")
+        .and_then(|servers| servers.get(0))
+        .ok_or("the specification has no servers, set base_url")?;
+    let mut url = server
+        .get("url")
+        .and_then(Value::as_str)
+        .ok_or("the first server has no url, set base_url")?
+        .to_string();
+    for (name, variable) in server
+        .get("variables")
+        .and_then(Value::as_mapping)
+        .into_iter()
+        .flatten()
+    {
+        if let (Some(name), Some(default)) = (
+            name.as_str(),
+            variable.get("default").and_then(Value::as_str),
+        ) {
+            url = url.replace(&format!("{{{}}}", name), default);
+        }
+    }
+
+    if !url.contains("://") {
+        return Err(format!("server url {} is relative, set base_url", url));
+    }
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+/// Follows a local reference such as `#/components/schemas/Pet`.
+fn lookup<'s>(spec: &'s Value, reference: &str) -> Option<&'s Value> {
+    reference
+        .strip_prefix("#/")?
+        .split('/')
+        .try_fold(spec, |value, token| {
+            value.get(token.replace("~1", "/").replace("~0", "~").as_str())
+        })
+}
+
+/// Inlines the references of `value`, so schemas are self-contained.
+///
+/// A reference to a schema that is already being inlined, as in recursive schemas, becomes an
+/// empty schema that accepts anything.
+fn resolve(spec: &Value, value: &Value, stack: &mut Vec<String>) -> Result<Value, String> {
+    match value {
+        Value::Mapping(mapping) => {
+            if let Some(reference) = mapping.get("$ref").and_then(Value::as_str) {
+                if stack.iter().any(|seen| seen == reference) {
+                    return Ok(Value::Mapping(Mapping::new()));
+                }
+                let target = lookup(spec, reference)
+                    .ok_or_else(|| format!("unresolved reference {}", reference))?;
+                stack.push(reference.to_string());
+                let resolved = resolve(spec, target, stack);
+                stack.pop();
+                return resolved;
+            }
+
+            mapping
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), resolve(spec, value, stack)?)))
+                .collect::<Result<Mapping, String>>()
+                .map(Value::Mapping)
+        }
+        Value::Sequence(items) => items
+            .iter()
+            .map(|item| resolve(spec, item, stack))
+            .collect::<Result<Vec<Value>, String>>()
+            .map(Value::Sequence),
+        other => Ok(other.clone()),
+    }
+}
+
+/// Matches a path against a pattern where `*` stands for any characters, e.g. `/pets/*`.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = path.strip_prefix(parts.next().unwrap_or("")) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Replaces the characters a name may not contain with `_`.
+fn identifier(text: &str, allow_dash: bool) -> String {
+    let replaced: String = text
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '_' || (allow_dash && c == '-') => c,
+            _ => '_',
+        })
+        .collect();
+
+    replaced
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("_")
+}
+
+fn is_json(media_type: &str) -> bool {
+    let essence = media_type.split(';').next().unwrap_or("").trim();
+    essence == "application/json" || essence.ends_with("+json")
+}
+
+/// Schema of the first JSON media type of a `content` map.
+fn json_schema(content: Option<&Value>) -> Option<Value> {
+    content
+        .and_then(Value::as_mapping)?
+        .iter()
+        .find(|(media_type, _)| media_type.as_str().is_some_and(is_json))
+        .map(|(_, media)| {
+            media
+                .get("schema")
+                .cloned()
+                .unwrap_or_else(|| Value::Mapping(Mapping::new()))
+        })
+}
+
+/// Condition of a template testing that an optional input is set, `false`, `0` and `""` included.
+fn is_set(property: &str) -> String {
+    format!("input.{} != null", property)
+}
+
+/// Separator of an optional query parameter when no required one comes first: `?` unless one
+/// of the optional parameters before it is set.
+fn optional_separator(previous: &[String]) -> String {
+    if previous.is_empty() {
+        return "?".to_string();
+    }
+
+    let mut separator = String::new();
+    for (idx, property) in previous.iter().enumerate() {
+        let tag = if idx == 0 { "if" } else { "elif" };
+        separator.push_str(&format!("{{% {} {} %}}&", tag, is_set(property)));
+    }
+    separator.push_str("{% else %}?{% endif %}");
+    separator
+}
+
+fn describe(schema: &mut Value, description: Option<&Value>) {
+    if let (Value::Mapping(schema), Some(description)) = (schema, description)
+        && !schema.contains_key("description")
+    {
+        schema.insert("description".into(), description.clone());
+    }
+}
+
+struct Importer<'s> {
+    spec: &'s Value,
+    source: &'s OpenApiSource,
+    base_url: String,
+    names: HashSet<String>,
+    warnings: Vec<String>,
+}
+
+impl Importer<'_> {
+    fn import(&mut self) -> Result<Vec<Value>, String> {
+        let paths = self
+            .spec
+            .get("paths")
+            .and_then(Value::as_mapping)
+            .ok_or("the specification has no paths")?;
+
+        let mut tools = Vec::new();
+        for (path, item) in paths.iter() {
+            let Some(path) = path.as_str() else {
+                continue;
+            };
+
+            for method in UNSUPPORTED_METHODS {
+                if let Some(operation) = item.get(method)
+                    && self.is_selected(path, operation)
+                {
+                    self.warnings.push(format!(
+                        "Skipped {} {}: the method is not supported",
+                        method.to_uppercase(),
+                        path
+                    ));
+                }
+            }
+
+            for (method, http_method) in METHODS {
+                let Some(operation) = item.get(method) else {
+                    continue;
+                };
+                if !self.is_selected(path, operation) {
+                    continue;
+                }
+
+                match self.tool(path, http_method, item, operation) {
+                    Ok((name, _)) if self.names.contains(&name) => self.warnings.push(format!(
+                        "Skipped {} {}: duplicate tool name {}",
+                        http_method, path, name
+                    )),
+                    Ok((name, tool)) => {
+                        self.names.insert(name);
+                        tools.push(tool);
+                    }
+                    Err(err) => self
+                        .warnings
+                        .push(format!("Skipped {} {}: {}", http_method, path, err)),
+                }
+            }
+        }
+
+        Ok(tools)
+    }
+
+    fn is_selected(&self, path: &str, operation: &Value) -> bool {
+        let tags: Vec<&str> = operation
+            .get("tags")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        let any_tag = |filter: &Option<Vec<String>>| {
+            filter
+                .iter()
+                .flatten()
+                .any(|tag| tags.contains(&tag.as_str()))
+        };
+        let any_path = |filter: &Option<Vec<String>>| {
+            filter
+                .iter()
+                .flatten()
+                .any(|pattern| path_matches(pattern, path))
+        };
+
+        let source = self.source;
+        (source.include_tags.is_none() || any_tag(&source.include_tags))
+            && (source.include_paths.is_none() || any_path(&source.include_paths))
+            && !any_tag(&source.exclude_tags)
+            && !any_path(&source.exclude_paths)
+    }
+
+    /// Returns the name and config entry of the tool calling an operation.
+    fn tool(
+        &self,
+        path: &str,
+        method: &str,
+        item: &Value,
+        operation: &Value,
+    ) -> Result<(String, Value), String> {
+        let name = match operation.get("operationId").and_then(Value::as_str) {
+            Some(operation_id) => identifier(operation_id, true),
+            None => identifier(&format!("{}_{}", method.to_lowercase(), path), true),
+        };
+        let description = ["summary", "description"]
+            .iter()
+            .find_map(|key| operation.get(key).and_then(Value::as_str))
+            .map(|description| description.trim().to_string())
+            .unwrap_or_else(|| format!("{} {}", method, path));
+
+        // Operation parameters override path parameters with the same name and location
+        let mut parameters: Vec<Value> = Vec::new();
+        for parameter in item
+            .get("parameters")
+            .into_iter()
+            .chain(operation.get("parameters"))
+            .filter_map(Value::as_sequence)
+            .flatten()
+        {
+            let parameter = resolve(self.spec, parameter, &mut Vec::new())?;
+            let key =
+                |parameter: &Value| (parameter.get("name").cloned(), parameter.get("in").cloned());
+            parameters.retain(|existing| key(existing) != key(&parameter));
+            parameters.push(parameter);
+        }
+
+        let mut properties = Mapping::new();
+        let mut required = Vec::new();
+        let mut url = format!("{}{}", self.base_url, path);
+        let mut required_query = Vec::new();
+        let mut optional_query = Vec::new();
+        let mut headers = Mapping::new();
+
+        for parameter in parameters.iter() {
+            let name = parameter
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or("a parameter has no name")?;
+            let location = parameter.get("in").and_then(Value::as_str).unwrap_or("");
+            let is_required = location == "path"
+                || parameter
+                    .get("required")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+            let property = identifier(name, false);
+            let mut schema = parameter
+                .get("schema")
+                .cloned()
+                .unwrap_or_else(|| Value::Mapping(Mapping::new()));
+            describe(&mut schema, parameter.get("description"));
+
+            let value = match schema.get("type").and_then(Value::as_str) {
+                Some("array") => format!("{{{{ input.{} | join(\",\") | url_encode }}}}", property),
+                _ => format!("{{{{ input.{} | url_encode }}}}", property),
+            };
+            match location {
+                "path" => url = url.replace(&format!("{{{}}}", name), &value),
+                "query" => {
+                    let pair = format!("{}={}", urlencoding::encode(name), value);
+                    match is_required {
+                        true => required_query.push(pair),
+                        false => optional_query.push((property.clone(), pair)),
+                    }
+                }
+                "header" if IGNORED_HEADERS.contains(&name.to_lowercase().as_str()) => continue,
+                "header" if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() => {
+                    return Err(format!(
+                        "header parameter {} is not a valid header name",
+                        name
+                    ));
+                }
+                "header" => {
+                    let value = match is_required {
+                        true => format!("{{{{ input.{} }}}}", property),
+                        false => format!(
+                            "{{% if {} %}}{{{{ input.{} }}}}{{% endif %}}",
+                            is_set(&property),
+                            property
+                        ),
+                    };
+                    headers.insert(name.into(), value.into());
+                }
+                _ if is_required => {
+                    return Err(format!("{} parameter {} is not supported", location, name));
+                }
+                _ => continue,
+            }
+
+            properties.insert(property.clone().into(), schema);
+            if is_required {
+                required.push(Value::from(property));
+            }
+        }
+
+        // Required parameters come first, so `?` only depends on the optional ones without them
+        if !required_query.is_empty() {
+            url.push('?');
+            url.push_str(&required_query.join("&"));
+        }
+        let mut previous = Vec::new();
+        for (property, pair) in optional_query {
+            let separator = match required_query.is_empty() {
+                true => optional_separator(&previous),
+                false => "&".to_string(),
+            };
+            url.push_str(&format!(
+                "{{% if {} %}}{}{}{{% endif %}}",
+                is_set(&property),
+                separator,
+                pair
+            ));
+            previous.push(property);
+        }
+
+        let mut body = None;
+        if let Some(request_body) = operation.get("requestBody") {
+            let request_body = resolve(self.spec, request_body, &mut Vec::new())?;
+            let mut schema = json_schema(request_body.get("content"))
+                .ok_or("only JSON request bodies are supported")?;
+            describe(&mut schema, request_body.get("description"));
+            let is_required = request_body
+                .get("required")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+
+            properties.insert("body".into(), schema);
+            // HTTP tools leave out a body and headers that render empty
+            let (body_template, content_type) = match is_required {
+                true => {
+                    required.push("body".into());
+                    (
+                        "{{ input.body | json }}".to_string(),
+                        "application/json".to_string(),
+                    )
+                }
+                false => (
+                    format!(
+                        "{{% if {} %}}{{{{ input.body | json }}}}{{% endif %}}",
+                        is_set("body")
+                    ),
+                    format!("{{% if {} %}}application/json{{% endif %}}", is_set("body")),
+                ),
+            };
+            body = Some(body_template);
+            headers.insert("Content-Type".into(), content_type.into());
+        }
+
+        let mut source_headers: Vec<(&String, &String)> =
+            self.source.headers.iter().flatten().collect();
+        source_headers.sort();
+        for (name, value) in source_headers {
+            headers.insert(name.as_str().into(), value.as_str().into());
+        }
+
+        // MCP output schemas describe objects, so other responses are left undescribed
+        let mut output_schema = None;
+        let success = operation
+            .get("responses")
+            .and_then(Value::as_mapping)
+            .into_iter()
+            .flatten()
+            .find(|(status, _)| match status {
+                Value::String(status) => status.starts_with('2'),
+                Value::Number(status) => status.to_string().starts_with('2'),
+                _ => false,
+            });
+        if let Some((_, response)) = success {
+            let response = resolve(self.spec, response, &mut Vec::new())?;
+            output_schema = json_schema(response.get("content"))
+                .filter(|schema| schema.get("type").and_then(Value::as_str) == Some("object"));
+        }
+
+        let mut input_schema = Mapping::new();
+        input_schema.insert("type".into(), "object".into());
+        input_schema.insert("properties".into(), Value::Mapping(properties));
+        if !required.is_empty() {
+            input_schema.insert("required".into(), Value::Sequence(required));
+        }
+
+        let mut http_metadata = Mapping::new();
+        http_metadata.insert("url".into(), url.into());
+        http_metadata.insert("method".into(), method.into());
+        if !headers.is_empty() {
+            http_metadata.insert("headers".into(), Value::Mapping(headers));
+        }
+        if let Some(body) = body {
+            http_metadata.insert("body".into(), body.into());
+        }
+        http_metadata.insert("input_schema".into(), Value::Mapping(input_schema));
+        if let Some(output_schema) = output_schema {
+            http_metadata.insert("output_schema".into(), output_schema);
+        }
+
+        let mut tool = Mapping::new();
+        tool.insert("name".into(), name.as_str().into());
+        tool.insert("description".into(), description.into());
+        tool.insert("tool_type".into(), "HTTP".into());
+        tool.insert("template_mode".into(), "FULL".into());
+        tool.insert("http_metadata".into(), Value::Mapping(http_metadata));
+
+        Ok((name, Value::Mapping(tool)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::{ErrorMode, HttpMetadata};
+    use crate::core::http::{HttpTool, RenderedHttpRequest};
+    use crate::core::template::TemplateMode;
+    use rmcp::ErrorData;
+    use rmcp::model::ErrorCode;
+    use serde_json::json;
+
+    const SPEC: &str = r#"
+openapi: 3.0.0
+servers:
+  - url: https://api.example.com/v1/
+paths:
+  /pets:
+    get:
+      operationId: listPets
+      parameters:
+        - {name: limit, in: query, schema: {type: integer}}
+        - {name: verbose, in: query, schema: {type: boolean}}
+        - {name: X-Trace, in: header, schema: {type: string}}
+    post:
+      operationId: createPet
+      parameters:
+        - {name: kind, in: query, required: true, schema: {type: string}}
+        - {name: tags, in: query, schema: {type: array, items: {type: string}}}
+      requestBody:
+        content:
+          application/json:
+            schema: {$ref: '#/components/schemas/Pet'}
+  /pets/{petId}:
+    put:
+      operationId: updatePet
+      parameters:
+        - {name: petId, in: path, schema: {type: string}}
+      requestBody:
+        required: true
+        content:
+          application/json:
+            schema: {$ref: '#/components/schemas/Pet'}
+    patch:
+      operationId: patchPet
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        name: {type: string}
+"#;
+
+    fn import_spec() -> (Vec<Value>, Vec<String>) {
+        import_text(SPEC, &OpenApiSource::default()).unwrap()
+    }
+
+    fn render(tools: &[Value], name: &str, input: serde_json::Value) -> RenderedHttpRequest {
+        try_render(tools, name, input).unwrap()
+    }
+
+    fn try_render(
+        tools: &[Value],
+        name: &str,
+        input: serde_json::Value,
+    ) -> Result<RenderedHttpRequest, ErrorData> {
+        let tool = tools
+            .iter()
+            .find(|tool| tool.get("name").and_then(Value::as_str) == Some(name))
+            .unwrap();
+        let http_metadata: HttpMetadata =
+            serde_yaml::from_value(tool.get("http_metadata").cloned().unwrap()).unwrap();
+        let tool = HttpTool::new(
+            0,
+            http_metadata,
+            ErrorMode::RESULT,
+            None,
+            None,
+            TemplateMode::FULL,
+            None,
+        );
+        tool.render(&json!({ "input": input }))
+    }
+
+    fn header<'r>(request: &'r RenderedHttpRequest, name: &str) -> Option<&'r str> {
+        request
+            .headers
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[test]
+    fn imports_supported_operations_and_warns_about_others() {
+        let (tools, warnings) = import_spec();
+        let names: Vec<&str> = tools
+            .iter()
+            .filter_map(|tool| tool.get("name").and_then(Value::as_str))
+            .collect();
+        assert_eq!(names, ["listPets", "createPet", "updatePet"]);
+        assert_eq!(
+            warnings,
+            ["Skipped PATCH /pets/{petId}: the method is not supported"]
+        );
+    }
+
+    #[test]
+    fn optional_query_parameters_are_left_out_when_absent() {
+        let (tools, _) = import_spec();
+
+        let request = render(&tools, "listPets", json!({}));
+        assert_eq!(request.url, "https://api.example.com/v1/pets");
+
+        let request = render(&tools, "listPets", json!({ "verbose": true }));
+        assert_eq!(request.url, "https://api.example.com/v1/pets?verbose=true");
+
+        let request = render(&tools, "listPets", json!({ "limit": 0, "verbose": false }));
+        assert_eq!(
+            request.url,
+            "https://api.example.com/v1/pets?limit=0&verbose=false"
+        );
+    }
+
+    #[test]
+    fn optional_query_parameters_follow_required_ones() {
+        let (tools, _) = import_spec();
+
+        let request = render(&tools, "createPet", json!({ "kind": "cat dog" }));
+        assert_eq!(
+            request.url,
+            "https://api.example.com/v1/pets?kind=cat%20dog"
+        );
+
+        let request = render(
+            &tools,
+            "createPet",
+            json!({ "kind": "cat", "tags": ["a", "b"] }),
+        );
+        assert_eq!(
+            request.url,
+            "https://api.example.com/v1/pets?kind=cat&tags=a%2Cb"
+        );
+    }
+
+    #[test]
+    fn optional_headers_are_left_out_when_absent() {
+        let (tools, _) = import_spec();
+
+        let request = render(&tools, "listPets", json!({}));
+        assert_eq!(header(&request, "X-Trace"), None);
+
+        let request = render(&tools, "listPets", json!({ "X_Trace": "abc" }));
+        assert_eq!(header(&request, "X-Trace"), Some("abc"));
+    }
+
+    #[test]
+    fn operations_with_invalid_header_names_are_skipped() {
+        let spec = r#"
+openapi: 3.0.0
+servers: [{url: "https://api.example.com"}]
+paths:
+  /pets:
+    get:
+      operationId: listPets
+      parameters:
+        - {name: "X Trace", in: header, schema: {type: string}}
+"#;
+        let (tools, warnings) = import_text(spec, &OpenApiSource::default()).unwrap();
+        assert!(tools.is_empty());
+        assert_eq!(
+            warnings,
+            ["Skipped GET /pets: header parameter X Trace is not a valid header name"]
+        );
+    }
+
+    #[test]
+    fn invalid_header_values_fail_the_call_naming_the_header() {
+        let (tools, _) = import_spec();
+
+        let err =
+            try_render(&tools, "listPets", json!({ "X_Trace": "a\r\nX-Admin: 1" })).unwrap_err();
+        assert_eq!(err.code, ErrorCode::INVALID_PARAMS);
+        assert!(
+            err.message.starts_with("Invalid value for header X-Trace"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn optional_body_is_left_out_with_its_content_type_when_absent() {
+        let (tools, _) = import_spec();
+
+        let request = render(&tools, "createPet", json!({ "kind": "cat" }));
+        assert_eq!(request.body, None);
+        assert_eq!(header(&request, "Content-Type"), None);
+
+        let request = render(
+            &tools,
+            "createPet",
+            json!({ "kind": "cat", "body": { "name": "Tom" } }),
+        );
+        assert_eq!(request.body.as_deref(), Some(r#"{"name":"Tom"}"#));
+        assert_eq!(header(&request, "Content-Type"), Some("application/json"));
+    }
+
+    #[test]
+    fn path_parameters_and_required_body_are_rendered() {
+        let (tools, _) = import_spec();
+
+        let request = render(
+            &tools,
+            "updatePet",
+            json!({ "petId": "a/b", "body": { "name": "Tom" } }),
+        );
+        assert_eq!(request.url, "https://api.example.com/v1/pets/a%2Fb");
+        assert_eq!(request.body.as_deref(), Some(r#"{"name":"Tom"}"#));
+        assert_eq!(header(&request, "Content-Type"), Some("application/json"));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::core::config::ErrorMode;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use rmcp::ErrorData;
+use rmcp::model::{
+    AnnotateAble, CallToolResult, Content, ErrorCode, RawAudioContent, RawContent, ResourceContents,
+};
+use serde_json::Value;
+
+pub const DEFAULT_MAX_BINARY_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Reports a failure of the tool execution itself (upstream status, exit code, ...).
+///
+/// With `ErrorMode::RESULT` the failure is returned as an `isError` tool result that
+/// the model can read, otherwise it is surfaced as a protocol error.
+pub fn execution_error(
+    error_mode: &ErrorMode,
+    message: String,
+) -> Result<CallToolResult, ErrorData> {
+    match error_mode {
+        ErrorMode::PROTOCOL => Err(ErrorData::new(ErrorCode::INTERNAL_ERROR, message, None)),
+        ErrorMode::RESULT => Ok(CallToolResult::error(vec![Content::text(message)])),
+    }
+}
+
+/// Sets the structured content of a successful result to the JSON object its text content holds.
+///
+/// Error results are left without structured content, their message is not meant to match the
+/// output schema. A successful result whose text is not a JSON object is logged, since it breaks
+/// the schema the tool advertises.
+pub fn structured_content(tool_name: &str, mut result: CallToolResult) -> CallToolResult {
+    if result.structured_content.is_some() || result.is_error == Some(true) {
+        return result;
+    }
+
+    let text = result
+        .content
+        .iter()
+        .flatten()
+        .find_map(|content| match content.raw {
+            RawContent::Text(ref text) => Some(text.text.as_str()),
+            _ => None,
+        });
+    result.structured_content = text
+        .and_then(|text| serde_json::from_str::<Value>(text).ok())
+        .filter(Value::is_object);
+    if result.structured_content.is_none() {
+        tracing::warn!(
+            tool = tool_name,
+            "tool has an output schema but its result is not a JSON object"
+        );
+    }
+    result
+}
+
+pub fn json_content(value: Value) -> Result<Content, ErrorData> {
+    Content::json::<Value>(value).map_err(|err| {
+        ErrorData::new(
+            ErrorCode::INTERNAL_ERROR,
+            format!("Error while parsing content as json: {}", err),
+            None,
+        )
+    })
+}
+
+pub fn mime_essence(mime_type: &str) -> String {
+    mime_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase()
+}
+
+pub fn is_binary_mime_type(mime_type: &str) -> bool {
+    let essence = mime_essence(mime_type);
+    if essence.is_empty() {
+        return false;
+    }
+
+    let is_text = essence.starts_with("text/")
+        || essence.ends_with("json")
+        || essence.ends_with("xml")
+        || essence.ends_with("yaml")
+        || essence == "application/javascript"
+        || essence == "application/x-www-form-urlencoded";
+
+    !is_text
+}
+
+/// Wraps binary data as image, audio or embedded blob resource content, based on its MIME type.
+pub fn binary_content(data: &[u8], mime_type: &str, uri: &str) -> Content {
+    let essence = mime_essence(mime_type);
+    let encoded = BASE64_STANDARD.encode(data);
+
+    if essence.starts_with("image/") {
+        Content::image(encoded, essence)
+    } else if essence.starts_with("audio/") {
+        RawContent::Audio(
+            RawAudioContent {
+                data: encoded,
+                mime_type: essence,
+            }
+            .no_annotation(),
+        )
+        .no_annotation()
+    } else {
+        Content::resource(ResourceContents::BlobResourceContents {
+            uri: uri.to_string(),
+            mime_type: Some(essence),
+            blob: encoded,
+        })
+    }
+}
+
+/// Bytes read from a response body or process pipe, capped at an optional size limit.
+#[derive(Default)]
+pub struct BoundedOutput {
+    pub data: Vec<u8>,
+    pub truncated: bool,
+}
+
+impl BoundedOutput {
+    /// Appends a chunk, keeping at most `limit` bytes.
+    ///
+    /// Returns `true` once the limit has been exceeded.
+    pub fn push(&mut self, chunk: &[u8], limit: Option<u64>) -> bool {
+        let Some(limit) = limit else {
+            self.data.extend_from_slice(chunk);
+            return false;
+        };
+
+        let remaining = (limit as usize).saturating_sub(self.data.len());
+        if chunk.len() > remaining {
+            self.data.extend_from_slice(&chunk[..remaining]);
+            self.truncated = true;
+        } else {
+            self.data.extend_from_slice(chunk);
+        }
+        self.truncated
+    }
+
+    /// Returns the output as text, followed by a truncation marker if the limit was exceeded.
+    pub fn to_text(&self, limit: u64, original_size: Option<u64>) -> String {
+        let text = String::from_utf8_lossy(&self.data).to_string();
+        if !self.truncated {
+            return text;
+        }
+
+        let marker = match original_size {
+            Some(original_size) => format!(
+                "[Output truncated to {} bytes, original size {} bytes]",
+                limit, original_size
+            ),
+            None => format!(
+                "[Output truncated to {} bytes, original size more than {} bytes]",
+                limit, limit
+            ),
+        };
+        format!("{}\n{}", text, marker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_output_keeps_at_most_the_limit() {
+        let mut output = BoundedOutput::default();
+        assert!(!output.push(b"abc", Some(5)));
+        assert!(output.push(b"defg", Some(5)));
+        assert_eq!(output.data, b"abcde");
+        assert!(output.truncated);
+
+        let mut output = BoundedOutput::default();
+        assert!(!output.push(b"abcdefg", None));
+        assert_eq!(output.to_text(5, None), "abcdefg");
+    }
+
+    #[test]
+    fn truncated_text_ends_with_a_marker() {
+        let mut output = BoundedOutput::default();
+        output.push(b"abcdefg", Some(3));
+        assert_eq!(
+            output.to_text(3, Some(7)),
+            "abc\n[Output truncated to 3 bytes, original size 7 bytes]"
+        );
+        assert_eq!(
+            output.to_text(3, None),
+            "abc\n[Output truncated to 3 bytes, original size more than 3 bytes]"
+        );
+    }
+
+    #[test]
+    fn successful_json_objects_become_structured_content() {
+        let result = CallToolResult::success(vec![Content::text(r#"{"temp": 18}"#)]);
+        let result = structured_content("weather", result);
+        assert_eq!(
+            result.structured_content,
+            Some(serde_json::json!({"temp": 18}))
+        );
+    }
+
+    #[test]
+    fn error_results_have_no_structured_content() {
+        let result = CallToolResult::error(vec![Content::text(r#"{"error": "boom"}"#)]);
+        let result = structured_content("weather", result);
+        assert_eq!(result.structured_content, None);
+        assert_eq!(result.is_error, Some(true));
+    }
+
+    #[test]
+    fn non_object_results_have_no_structured_content() {
+        for text in ["[1, 2]", "18", "sunny"] {
+            let result = CallToolResult::success(vec![Content::text(text)]);
+            let result = structured_content("weather", result);
+            assert_eq!(result.structured_content, None, "{}", text);
+            assert_eq!(result.content.unwrap()[0].as_text().unwrap().text, text);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use crate::core::config::{PaginationConfig, PaginationStrategy};
+use reqwest::Url;
+use reqwest::header::{HeaderMap, LINK};
+use serde_json::Value;
+
+pub struct Paginator {
+    config: PaginationConfig,
+    pages: u32,
+    offset: u64,
+    items: Vec<Value>,
+}
+
+impl Paginator {
+    const DEFAULT_MAX_PAGES: u32 = 10;
+    const DEFAULT_START_PAGE: u64 = 1;
+
+    pub fn new(config: PaginationConfig) -> Self {
+        Self {
+            config,
+            pages: 0,
+            offset: 0,
+            items: vec![],
+        }
+    }
+
+    /// Looks up a dotted path such as `data.items` or `links.0.href` in a JSON value.
+    ///
+    /// An empty path returns the value itself.
+    pub fn value_at_path<'v>(value: &'v Value, path: &str) -> Option<&'v Value> {
+        path.split('.')
+            .filter(|segment| !segment.is_empty())
+            .try_fold(value, |current, segment| match current {
+                Value::Object(map) => map.get(segment),
+                Value::Array(array) => segment.parse::<usize>().ok().and_then(|idx| array.get(idx)),
+                _ => None,
+            })
+    }
+
+    /// Returns the `rel="next"` target of an RFC 8288 `Link` header, if any.
+    fn next_link(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get_all(LINK)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find_map(|link| {
+                let mut parts = link.split(';');
+                let target = parts.next()?.trim();
+                let is_next = parts.any(|param| {
+                    let param = param.trim().replace(' ', "");
+                    param == "rel=\"next\"" || param == "rel=next"
+                });
+                if !is_next {
+                    return None;
+                }
+                Some(
+                    target
+                        .trim_start_matches('<')
+                        .trim_end_matches('>')
+                        .to_string(),
+                )
+            })
+    }
+
+    fn with_query_param(url: &Url, name: &str, value: &str) -> Url {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != name)
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+
+        let mut next_url = url.clone();
+        next_url
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(pairs)
+            .append_pair(name, value);
+        next_url
+    }
+
+    fn required_param<'c>(param: &'c Option<String>, field: &str) -> Result<&'c str, String> {
+        param
+            .as_deref()
+            .ok_or_else(|| format!("pagination.{} is required for this strategy", field))
+    }
+
+    /// Collects the items of a fetched page and returns the url of the next page.
+    ///
+    /// `None` is returned once the upstream has no more pages or one of the
+    /// configured page/item limits has been reached.
+    pub fn add_page(
+        &mut self,
+        url: &str,
+        headers: &HeaderMap,
+        body: Value,
+    ) -> Result<Option<String>, String> {
+        let items_path = self.config.items_path.clone().unwrap_or_default();
+        let items = match Self::value_at_path(&body, &items_path) {
+            Some(Value::Array(items)) => items.clone(),
+            _ => {
+                return Err(format!(
+                    "Expected an array at items path '{}' in the response from {}",
+                    items_path, url
+                ));
+            }
+        };
+
+        self.pages += 1;
+        let page_item_count = items.len() as u64;
+        self.offset += page_item_count;
+        self.items.extend(items);
+
+        if let Some(max_items) = self.config.max_items
+            && self.items.len() >= max_items
+        {
+            self.items.truncate(max_items);
+            return Ok(None);
+        }
+
+        let max_pages = self.config.max_pages.unwrap_or(Self::DEFAULT_MAX_PAGES);
+        if page_item_count == 0 || self.pages >= max_pages {
+            return Ok(None);
+        }
+
+        let current_url = Url::parse(url).map_err(|err| format!("Invalid url {}: {}", url, err))?;
+
+        let next_url = match self.config.strategy {
+            PaginationStrategy::LINK_HEADER => match Self::next_link(headers) {
+                Some(target) => Some(
+                    current_url
+                        .join(&target)
+                        .map_err(|err| format!("Invalid next link {}: {}", target, err))?,
+                ),
+                None => None,
+            },
+            PaginationStrategy::CURSOR => {
+                let cursor_path = Self::required_param(&self.config.cursor_path, "cursor_path")?;
+                let cursor = match Self::value_at_path(&body, cursor_path) {
+                    Some(Value::String(cursor)) if !cursor.is_empty() => cursor.clone(),
+                    Some(Value::Number(cursor)) => cursor.to_string(),
+                    _ => return Ok(None),
+                };
+
+                if cursor.starts_with("http://")
+                    || cursor.starts_with("https://")
+                    || cursor.starts_with('/')
+                {
+                    // The cursor is the next page url itself
+                    Some(
+                        current_url
+                            .join(&cursor)
+                            .map_err(|err| format!("Invalid next url {}: {}", cursor, err))?,
+                    )
+                } else {
+                    let cursor_param =
+                        Self::required_param(&self.config.cursor_param, "cursor_param")?;
+                    Some(Self::with_query_param(&current_url, cursor_param, &cursor))
+                }
+            }
+            PaginationStrategy::PAGE => {
+                let page_param = Self::required_param(&self.config.page_param, "page_param")?;
+                let start_page = self.config.start_page.unwrap_or(Self::DEFAULT_START_PAGE);
+                let next_page = start_page + self.pages as u64;
+                Some(Self::with_query_param(
+                    &current_url,
+                    page_param,
+                    &next_page.to_string(),
+                ))
+            }
+            PaginationStrategy::OFFSET => {
+                let offset_param = Self::required_param(&self.config.offset_param, "offset_param")?;
+                Some(Self::with_query_param(
+                    &current_url,
+                    offset_param,
+                    &self.offset.to_string(),
+                ))
+            }
+        };
+
+        Ok(next_url.map(|url| url.to_string()))
+    }
+
+    pub fn into_items(self) -> Value {
+        Value::Array(self.items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use serde_json::json;
+
+    fn paginator(config: Value) -> Paginator {
+        Paginator::new(serde_json::from_value(config).unwrap())
+    }
+
+    fn next(paginator: &mut Paginator, url: &str, body: Value) -> Option<String> {
+        paginator.add_page(url, &HeaderMap::new(), body).unwrap()
+    }
+
+    #[test]
+    fn link_header_follows_the_next_link() {
+        let mut paginator = paginator(json!({"strategy": "LINK_HEADER"}));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LINK,
+            HeaderValue::from_static(r#"</items?page=1>; rel="prev", </items?page=3>; rel="next""#),
+        );
+
+        let next = paginator
+            .add_page("https://api.test/items?page=2", &headers, json!([1]))
+            .unwrap();
+        assert_eq!(next.as_deref(), Some("https://api.test/items?page=3"));
+
+        let next = paginator
+            .add_page(
+                "https://api.test/items?page=3",
+                &HeaderMap::new(),
+                json!([2]),
+            )
+            .unwrap();
+        assert_eq!(next, None);
+        assert_eq!(paginator.into_items(), json!([1, 2]));
+    }
+
+    #[test]
+    fn cursor_sets_the_cursor_param_or_follows_a_cursor_url() {
+        let mut paginator = paginator(json!({
+            "strategy": "CURSOR",
+            "items_path": "data",
+            "cursor_path": "meta.next",
+            "cursor_param": "after",
+        }));
+
+        let page = json!({"data": [1], "meta": {"next": "abc"}});
+        assert_eq!(
+            next(&mut paginator, "https://api.test/items?after=x&q=1", page).as_deref(),
+            Some("https://api.test/items?q=1&after=abc")
+        );
+        let page = json!({"data": [2], "meta": {"next": "/items?token=2"}});
+        assert_eq!(
+            next(&mut paginator, "https://api.test/items?q=1&after=abc", page).as_deref(),
+            Some("https://api.test/items?token=2")
+        );
+        let page = json!({"data": [3], "meta": {"next": ""}});
+        assert_eq!(
+            next(&mut paginator, "https://api.test/items?token=2", page),
+            None
+        );
+        assert_eq!(paginator.into_items(), json!([1, 2, 3]));
+    }
+
+    #[test]
+    fn page_counts_up_from_the_start_page() {
+        let mut paginator = paginator(json!({
+            "strategy": "PAGE",
+            "page_param": "page",
+            "start_page": 0,
+        }));
+
+        assert_eq!(
+            next(&mut paginator, "https://api.test/items", json!([1])).as_deref(),
+            Some("https://api.test/items?page=1")
+        );
+        assert_eq!(
+            next(&mut paginator, "https://api.test/items?page=1", json!([2])).as_deref(),
+            Some("https://api.test/items?page=2")
+        );
+        assert_eq!(
+            next(&mut paginator, "https://api.test/items?page=2", json!([])),
+            None
+        );
+    }
+
+    #[test]
+    fn offset_advances_by_the_items_read() {
+        let mut paginator = paginator(json!({"strategy": "OFFSET", "offset_param": "offset"}));
+
+        assert_eq!(
+            next(&mut paginator, "https://api.test/items", json!([1, 2])).as_deref(),
+            Some("https://api.test/items?offset=2")
+        );
+        assert_eq!(
+            next(
+                &mut paginator,
+                "https://api.test/items?offset=2",
+                json!([3])
+            )
+            .as_deref(),
+            Some("https://api.test/items?offset=3")
+        );
+    }
+
+    #[test]
+    fn max_pages_stops_after_that_many_pages() {
+        let mut paginator = paginator(json!({
+            "strategy": "PAGE",
+            "page_param": "page",
+            "max_pages": 2,
+        }));
+
+        assert!(next(&mut paginator, "https://api.test/items", json!([1])).is_some());
+        assert_eq!(
+            next(&mut paginator, "https://api.test/items?page=2", json!([2])),
+            None
+        );
+        assert_eq!(paginator.into_items(), json!([1, 2]));
+    }
+
+    #[test]
+    fn max_items_truncates_the_last_page() {
+        let mut paginator = paginator(json!({
+            "strategy": "OFFSET",
+            "offset_param": "offset",
+            "max_items": 3,
+        }));
+
+        assert!(next(&mut paginator, "https://api.test/items", json!([1, 2])).is_some());
+        assert_eq!(
+            next(
+                &mut paginator,
+                "https://api.test/items?offset=2",
+                json!([3, 4])
+            ),
+            None
+        );
+        assert_eq!(paginator.into_items(), json!([1, 2, 3]));
+    }
+
+    #[test]
+    fn misconfigured_pages_are_errors() {
+        let mut paginator = paginator(json!({"strategy": "PAGE", "items_path": "data"}));
+        assert_eq!(
+            paginator.add_page("https://api.test/items", &HeaderMap::new(), json!({"data": {}})),
+            Err("Expected an array at items path 'data' in the response from https://api.test/items".to_string())
+        );
+        assert_eq!(
+            paginator.add_page(
+                "https://api.test/items",
+                &HeaderMap::new(),
+                json!({"data": [1]})
+            ),
+            Err("pagination.page_param is required for this strategy".to_string())
+        );
+    }
+}
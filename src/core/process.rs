@@ -0,0 +1,61 @@
+/// Guard over the process group of a spawned child.
+///
+/// The child is expected to be spawned as the leader of a new process group, so
+/// killing the group also terminates every process the command started. The group
+/// is killed when the guard is dropped while still armed, e.g. when the future
+/// driving the command is dropped.
+pub struct ProcessGroup {
+    pgid: Option<u32>,
+    armed: bool,
+}
+
+impl ProcessGroup {
+    pub fn new(pgid: Option<u32>) -> Self {
+        Self { pgid, armed: true }
+    }
+
+    /// Configures a command to be spawned as the leader of a new process group.
+    pub fn configure(command: &mut tokio::process::Command) {
+        #[cfg(unix)]
+        command.process_group(0);
+        // Without process groups, fall back to killing the direct child only
+        command.kill_on_drop(true);
+    }
+
+    /// Asks every process in the group to exit with SIGTERM, the guard stays armed.
+    pub fn terminate(&self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.pgid {
+            // SAFETY: killpg has no memory safety requirements, an invalid group only yields ESRCH
+            unsafe {
+                libc::killpg(pgid as libc::pid_t, libc::SIGTERM);
+            }
+        }
+    }
+
+    /// Sends SIGKILL to every process in the group and disarms the guard.
+    pub fn kill(&mut self) {
+        self.armed = false;
+
+        #[cfg(unix)]
+        if let Some(pgid) = self.pgid {
+            // SAFETY: killpg has no memory safety requirements, an invalid group only yields ESRCH
+            unsafe {
+                libc::killpg(pgid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+
+    /// Leaves the group running when the guard is dropped.
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if self.armed {
+            self.kill();
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::core::config::StreamOutput;
+use rmcp::RoleServer;
+use rmcp::model::{
+    LoggingLevel, LoggingMessageNotificationParam, ProgressNotificationParam, ProgressToken,
+};
+use rmcp::service::{Peer, RequestContext};
+use serde_json::json;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Forwards lines of command output to the client while the command is still running.
+#[derive(Clone)]
+pub struct OutputNotifier {
+    peer: Peer<RoleServer>,
+    progress_token: Option<ProgressToken>,
+    log: bool,
+    logger: String,
+    lines: Arc<AtomicU64>,
+}
+
+impl OutputNotifier {
+    pub const STDOUT: &'static str = "stdout";
+    pub const STDERR: &'static str = "stderr";
+
+    /// Returns a notifier if the tool streams its output where the client can receive it.
+    ///
+    /// Progress notifications need the request to carry a progress token, log messages do not.
+    pub fn new(
+        stream_output: &StreamOutput,
+        logger: &str,
+        request_context: &RequestContext<RoleServer>,
+    ) -> Option<Self> {
+        let progress_token = matches!(stream_output, StreamOutput::PROGRESS | StreamOutput::ALL)
+            .then(|| request_context.meta.get_progress_token())
+            .flatten();
+        let log = matches!(stream_output, StreamOutput::LOG | StreamOutput::ALL);
+        if progress_token.is_none() && !log {
+            return None;
+        }
+
+        Some(Self {
+            peer: request_context.peer.clone(),
+            progress_token,
+            log,
+            logger: logger.to_string(),
+            lines: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Sends one line of output as a progress notification and/or a log message.
+    pub async fn notify_line(&self, stream: &str, line: &str) {
+        let progress = self.lines.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(ref progress_token) = self.progress_token {
+            let notified = self
+                .peer
+                .notify_progress(ProgressNotificationParam {
+                    progress_token: progress_token.clone(),
+                    progress: progress as f64,
+                    total: None,
+                    message: Some(format!("[{}] {}", stream, line)),
+                })
+                .await;
+            if let Err(err) = notified {
+                tracing::warn!(error = %err, "Error while sending a progress notification");
+            }
+        }
+
+        if self.log {
+            let level = if stream == Self::STDERR {
+                LoggingLevel::Warning
+            } else {
+                LoggingLevel::Info
+            };
+            let notified = self
+                .peer
+                .notify_logging_message(LoggingMessageNotificationParam {
+                    level,
+                    logger: Some(self.logger.clone()),
+                    data: json!({ "stream": stream, "line": line }),
+                })
+                .await;
+            if let Err(err) = notified {
+                tracing::warn!(error = %err, "Error while sending a log notification");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::config::DynamicMCPConfig;
+    use crate::core::engine::DynamicMCP;
+    use rmcp::ServiceExt;
+    use serde_json::{Value, json};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    #[tokio::test]
+    async fn log_messages_are_sent_without_a_progress_token() {
+        let config: DynamicMCPConfig = serde_yaml::from_str(
+            r#"
+tools:
+  - name: greet
+    description: greet
+    tool_type: COMMAND
+    command_metadata:
+      command: echo
+      args: ["hello"]
+      stream_output: LOG
+      input_schema:
+        type: object
+"#,
+        )
+        .unwrap();
+
+        let (server_stream, client_stream) = tokio::io::duplex(64 * 1024);
+        let service = DynamicMCP::new(config).new_session();
+        tokio::spawn(async move {
+            if let Ok(server) = service.serve(tokio::io::split(server_stream)).await {
+                let _ = server.waiting().await;
+            }
+        });
+
+        // Raw messages, as the rmcp client would add a progress token to the call
+        let (reader, mut writer) = tokio::io::split(client_stream);
+        let mut lines = BufReader::new(reader).lines();
+        for message in [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+                "protocolVersion": "2025-03-26",
+                "capabilities": {},
+                "clientInfo": {"name": "test", "version": "0"},
+            }}),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": {
+                "name": "greet",
+                "arguments": {},
+            }}),
+        ] {
+            writer
+                .write_all(format!("{}\n", message).as_bytes())
+                .await
+                .unwrap();
+        }
+
+        let mut log_messages = vec![];
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let message: Value = serde_json::from_str(&line).unwrap();
+            if message["method"] == json!("notifications/message") {
+                log_messages.push(message["params"]["data"].clone());
+            }
+            if message["id"] == json!(2) {
+                break;
+            }
+        }
+        assert_eq!(
+            log_messages,
+            vec![json!({"stream": "stdout", "line": "hello"})]
+        );
+    }
+}
@@ -0,0 +1,579 @@
+use crate::core::config::SandboxConfig;
+use std::path::Path;
+
+#[cfg(target_os = "linux")]
+use std::ffi::{CStr, CString};
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+
+/// Isolation applied to a command between fork and exec.
+///
+/// The child enters new user and mount namespaces, and a new network namespace unless
+/// `network` is allowed. Inside them it mounts the configured read-only binds and tmpfs
+/// directories, lowers its resource limits, sets `no_new_privs` and installs a seccomp
+/// filter. User namespaces make all of this work without privileges.
+#[derive(Clone)]
+pub struct Sandbox {
+    #[cfg(target_os = "linux")]
+    spec: Arc<linux::SandboxSpec>,
+}
+
+impl Sandbox {
+    pub fn new(tool_index: usize, sandbox_config: SandboxConfig) -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            Self {
+                spec: Arc::new(linux::SandboxSpec::new(tool_index, sandbox_config)),
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = sandbox_config;
+            panic!(
+                "Error creating sandbox, tool index {}: sandbox is only supported on Linux",
+                tool_index
+            );
+        }
+    }
+
+    /// Registers the sandbox setup to run in the child before the command is executed.
+    ///
+    /// `cwd` is entered again once the mounts are in place, so a working directory below
+    /// a read-only path sees the read-only mount.
+    pub fn configure(&self, command: &mut tokio::process::Command, cwd: Option<&Path>) {
+        #[cfg(target_os = "linux")]
+        {
+            let spec = self.spec.clone();
+            let child_setup = linux::ChildSetup::new(cwd);
+
+            // SAFETY: the closure runs between fork and exec, and `SandboxSpec::enter` only
+            // issues raw system calls on data prepared before the fork, without allocating
+            unsafe {
+                command.pre_exec(move || spec.enter(&child_setup));
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (command, cwd);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use crate::core::config::ResourceLimits;
+
+    const AUDIT_ARCH_X86_64: u32 = 0xC000_003E;
+    const AUDIT_ARCH_AARCH64: u32 = 0xC000_00B7;
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+    const ST_RELATIME: libc::c_ulong = 4096;
+
+    // Offsets of `nr`, `arch` and the low half of `args[0]` in `struct seccomp_data`,
+    // on the little-endian architectures supported below
+    const SECCOMP_DATA_NR_OFFSET: u32 = 0;
+    const SECCOMP_DATA_ARCH_OFFSET: u32 = 4;
+    const SECCOMP_DATA_ARG0_OFFSET: u32 = 16;
+
+    /// `clone` flags that create namespaces, the same escape as `unshare`.
+    const CLONE_NAMESPACE_FLAGS: libc::c_int = libc::CLONE_NEWNS
+        | libc::CLONE_NEWCGROUP
+        | libc::CLONE_NEWUTS
+        | libc::CLONE_NEWIPC
+        | libc::CLONE_NEWUSER
+        | libc::CLONE_NEWPID
+        | libc::CLONE_NEWNET;
+
+    /// System calls a sandboxed command never needs, which fail with `EPERM`.
+    const BLOCKED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_setns,
+        libc::SYS_unshare,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_kexec_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_acct,
+        libc::SYS_quotactl,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_settimeofday,
+        libc::SYS_clock_settime,
+        libc::SYS_sethostname,
+        libc::SYS_setdomainname,
+    ];
+
+    pub struct SandboxSpec {
+        network: bool,
+        read_only_paths: Vec<CString>,
+        tmpfs_paths: Vec<CString>,
+        no_new_privileges: bool,
+        seccomp_filter: Option<Vec<libc::sock_filter>>,
+        limits: Vec<(libc::c_int, u64)>,
+    }
+
+    /// Per-spawn data for the child, prepared in the parent because the child must not allocate.
+    pub struct ChildSetup {
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+        cwd: Option<CString>,
+    }
+
+    impl ChildSetup {
+        pub fn new(cwd: Option<&Path>) -> Self {
+            // SAFETY: getuid and getgid cannot fail and have no memory safety requirements
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+            let cwd = cwd
+                .map(Path::to_path_buf)
+                .or_else(|| std::env::current_dir().ok())
+                .and_then(|cwd| CString::new(cwd.to_string_lossy().as_bytes()).ok());
+
+            Self {
+                // Keep the same ids inside the namespace, so the command has no capabilities after exec
+                uid_map: format!("{} {} 1", uid, uid).into_bytes(),
+                gid_map: format!("{} {} 1", gid, gid).into_bytes(),
+                cwd,
+            }
+        }
+    }
+
+    impl SandboxSpec {
+        pub fn new(tool_index: usize, sandbox_config: SandboxConfig) -> Self {
+            let to_c_paths = |paths: Option<Vec<String>>, field: &str| -> Vec<CString> {
+                paths
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|path| {
+                        if !Path::new(&path).is_absolute() {
+                            panic!(
+                                "Error creating sandbox, tool index {}: {} entry {} is not an absolute path",
+                                tool_index, field, path
+                            );
+                        }
+                        CString::new(path.clone()).unwrap_or_else(|_| {
+                            panic!(
+                                "Error creating sandbox, tool index {}: {} entry {} contains a NUL byte",
+                                tool_index, field, path
+                            )
+                        })
+                    })
+                    .collect()
+            };
+
+            let seccomp_filter = if sandbox_config.seccomp.unwrap_or(true) {
+                Some(Self::seccomp_filter().unwrap_or_else(|| {
+                    panic!(
+                        "Error creating sandbox, tool index {}: seccomp is not supported on this architecture, set `seccomp: false`",
+                        tool_index
+                    )
+                }))
+            } else {
+                None
+            };
+
+            Self {
+                network: sandbox_config.network.unwrap_or(false),
+                read_only_paths: to_c_paths(sandbox_config.read_only_paths, "read_only_paths"),
+                tmpfs_paths: to_c_paths(sandbox_config.tmpfs_paths, "tmpfs_paths"),
+                no_new_privileges: sandbox_config.no_new_privileges.unwrap_or(true),
+                seccomp_filter,
+                limits: Self::resource_limits(sandbox_config.limits.unwrap_or_default()),
+            }
+        }
+
+        fn resource_limits(limits: ResourceLimits) -> Vec<(libc::c_int, u64)> {
+            [
+                (libc::RLIMIT_CPU as libc::c_int, limits.cpu_seconds),
+                (libc::RLIMIT_AS as libc::c_int, limits.memory_bytes),
+                (libc::RLIMIT_FSIZE as libc::c_int, limits.file_size_bytes),
+                (libc::RLIMIT_NPROC as libc::c_int, limits.max_processes),
+            ]
+            .into_iter()
+            .filter_map(|(resource, limit)| limit.map(|limit| (resource, limit)))
+            .collect()
+        }
+
+        fn statement(code: u32, k: u32) -> libc::sock_filter {
+            libc::sock_filter {
+                code: code as u16,
+                jt: 0,
+                jf: 0,
+                k,
+            }
+        }
+
+        fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+            libc::sock_filter {
+                code: code as u16,
+                jt,
+                jf,
+                k,
+            }
+        }
+
+        /// Builds a BPF program that denies `BLOCKED_SYSCALLS` and `clone` with namespace flags,
+        /// and allows everything else.
+        ///
+        /// `clone3` passes its flags in memory a filter cannot read, so it fails with `ENOSYS`
+        /// and the C library falls back to `clone`.
+        ///
+        /// Returns `None` on architectures without a known audit arch value.
+        pub fn seccomp_filter() -> Option<Vec<libc::sock_filter>> {
+            let arch = if cfg!(target_arch = "x86_64") {
+                AUDIT_ARCH_X86_64
+            } else if cfg!(target_arch = "aarch64") {
+                AUDIT_ARCH_AARCH64
+            } else {
+                return None;
+            };
+
+            let deny = libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA);
+            let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+            let jump_if_equal = libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K;
+            let ret = libc::BPF_RET | libc::BPF_K;
+
+            let mut filter = vec![
+                // System calls of another architecture would bypass the numbers below
+                Self::statement(load, SECCOMP_DATA_ARCH_OFFSET),
+                Self::jump(jump_if_equal, arch, 1, 0),
+                Self::statement(ret, libc::SECCOMP_RET_KILL_PROCESS),
+                Self::statement(load, SECCOMP_DATA_NR_OFFSET),
+            ];
+
+            if cfg!(target_arch = "x86_64") {
+                // The x32 ABI reuses the x86_64 audit arch with offset system call numbers
+                filter.push(Self::jump(
+                    libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                    X32_SYSCALL_BIT,
+                    0,
+                    1,
+                ));
+                filter.push(Self::statement(ret, deny));
+            }
+
+            for syscall in BLOCKED_SYSCALLS {
+                filter.push(Self::jump(jump_if_equal, *syscall as u32, 0, 1));
+                filter.push(Self::statement(ret, deny));
+            }
+
+            let not_implemented =
+                libc::SECCOMP_RET_ERRNO | (libc::ENOSYS as u32 & libc::SECCOMP_RET_DATA);
+            filter.push(Self::jump(jump_if_equal, libc::SYS_clone3 as u32, 0, 1));
+            filter.push(Self::statement(ret, not_implemented));
+
+            // Loads the flags of `clone` over the system call number, so it comes last
+            filter.push(Self::jump(jump_if_equal, libc::SYS_clone as u32, 0, 3));
+            filter.push(Self::statement(load, SECCOMP_DATA_ARG0_OFFSET));
+            filter.push(Self::jump(
+                libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K,
+                CLONE_NAMESPACE_FLAGS as u32,
+                0,
+                1,
+            ));
+            filter.push(Self::statement(ret, deny));
+
+            filter.push(Self::statement(ret, libc::SECCOMP_RET_ALLOW));
+            Some(filter)
+        }
+
+        fn check(result: libc::c_int) -> io::Result<()> {
+            if result < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn write_file(path: &CStr, data: &[u8]) -> io::Result<()> {
+            // SAFETY: `path` is NUL terminated and `data` outlives the write call
+            unsafe {
+                let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                Self::check(fd)?;
+                let written = libc::write(fd, data.as_ptr().cast(), data.len());
+                libc::close(fd);
+                if written < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        }
+
+        /// Flags a read-only remount has to keep, the kernel refuses to clear them in a user namespace.
+        fn locked_mount_flags(path: &CStr) -> libc::c_ulong {
+            // SAFETY: `stat` is plain old data filled in by statvfs, `path` is NUL terminated
+            let flags = unsafe {
+                let mut stat: libc::statvfs = std::mem::zeroed();
+                if libc::statvfs(path.as_ptr(), &mut stat) < 0 {
+                    return 0;
+                }
+                stat.f_flag
+            };
+
+            [
+                (libc::ST_NOSUID, libc::MS_NOSUID),
+                (libc::ST_NODEV, libc::MS_NODEV),
+                (libc::ST_NOEXEC, libc::MS_NOEXEC),
+                (libc::ST_NOATIME, libc::MS_NOATIME),
+                (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+                (ST_RELATIME, libc::MS_RELATIME),
+            ]
+            .iter()
+            .filter(|(statvfs_flag, _)| flags & statvfs_flag != 0)
+            .fold(0, |mount_flags, (_, mount_flag)| mount_flags | mount_flag)
+        }
+
+        /// Runs in the forked child right before exec.
+        pub fn enter(&self, child_setup: &ChildSetup) -> io::Result<()> {
+            let mut namespaces = libc::CLONE_NEWUSER | libc::CLONE_NEWNS;
+            if !self.network {
+                namespaces |= libc::CLONE_NEWNET;
+            }
+
+            // SAFETY: every call below is a raw system call on NUL terminated strings or
+            // plain old data prepared before the fork
+            unsafe {
+                Self::check(libc::unshare(namespaces))?;
+
+                Self::write_file(c"/proc/self/setgroups", b"deny")?;
+                Self::write_file(c"/proc/self/uid_map", &child_setup.uid_map)?;
+                Self::write_file(c"/proc/self/gid_map", &child_setup.gid_map)?;
+
+                // Keep the mounts below from propagating back to the host
+                Self::check(libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ))?;
+
+                for path in self.tmpfs_paths.iter() {
+                    Self::check(libc::mount(
+                        c"tmpfs".as_ptr(),
+                        path.as_ptr(),
+                        c"tmpfs".as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV,
+                        std::ptr::null(),
+                    ))?;
+                }
+
+                for path in self.read_only_paths.iter() {
+                    Self::check(libc::mount(
+                        path.as_ptr(),
+                        path.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REC,
+                        std::ptr::null(),
+                    ))?;
+                    Self::check(libc::mount(
+                        std::ptr::null(),
+                        path.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND
+                            | libc::MS_REMOUNT
+                            | libc::MS_RDONLY
+                            | Self::locked_mount_flags(path),
+                        std::ptr::null(),
+                    ))?;
+                }
+
+                if let Some(ref cwd) = child_setup.cwd {
+                    Self::check(libc::chdir(cwd.as_ptr()))?;
+                }
+
+                for (resource, limit) in self.limits.iter() {
+                    let rlimit = libc::rlimit {
+                        rlim_cur: *limit as libc::rlim_t,
+                        rlim_max: *limit as libc::rlim_t,
+                    };
+                    Self::check(libc::setrlimit(*resource as _, &rlimit))?;
+                }
+
+                // Unprivileged processes may only install a seccomp filter with no_new_privs set
+                if self.no_new_privileges || self.seccomp_filter.is_some() {
+                    Self::check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+                }
+
+                if let Some(ref filter) = self.seccomp_filter {
+                    let program = libc::sock_fprog {
+                        len: filter.len() as libc::c_ushort,
+                        filter: filter.as_ptr() as *mut libc::sock_filter,
+                    };
+                    Self::check(libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &program as *const libc::sock_fprog,
+                    ))?;
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use std::process::Output;
+
+    /// Sandboxes rely on unprivileged user namespaces, which some CI hosts disable.
+    fn user_namespaces_available() -> bool {
+        let available = std::process::Command::new("unshare")
+            .args(["-U", "true"])
+            .status()
+            .is_ok_and(|status| status.success());
+        if !available {
+            eprintln!("skipping sandbox test, user namespaces are not available");
+        }
+        available
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("easymcp-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn run_sandboxed(sandbox_config: serde_json::Value, script: &str) -> Output {
+        let sandbox = Sandbox::new(0, serde_json::from_value(sandbox_config).unwrap());
+        let mut command = tokio::process::Command::new("sh");
+        command.args(["-c", script]);
+        sandbox.configure(&mut command, None);
+        command.output().await.unwrap()
+    }
+
+    #[test]
+    fn seccomp_filter_checks_the_architecture_and_allows_by_default() {
+        let Some(filter) = linux::SandboxSpec::seccomp_filter() else {
+            return;
+        };
+        assert_eq!(filter[0].k, 4);
+        assert_eq!(filter[2].k, libc::SECCOMP_RET_KILL_PROCESS);
+        assert_eq!(filter.last().unwrap().k, libc::SECCOMP_RET_ALLOW);
+    }
+
+    #[tokio::test]
+    async fn network_is_cut_off_by_default() {
+        if !user_namespaces_available() {
+            return;
+        }
+        let output = run_sandboxed(json!({}), "tail -n +3 /proc/net/dev").await;
+        assert!(output.status.success(), "{:?}", output);
+        let interfaces = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split(':').next())
+            .map(|name| name.trim().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(interfaces, vec!["lo"]);
+    }
+
+    #[tokio::test]
+    async fn read_only_paths_cannot_be_written() {
+        if !user_namespaces_available() {
+            return;
+        }
+        let dir = temp_dir("sandbox-read-only");
+        let output = run_sandboxed(
+            json!({"read_only_paths": [dir]}),
+            &format!("touch {}/file", dir.display()),
+        )
+        .await;
+        assert!(!output.status.success());
+        assert!(!dir.join("file").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn tmpfs_paths_hide_writes_from_the_host() {
+        if !user_namespaces_available() {
+            return;
+        }
+        let dir = temp_dir("sandbox-tmpfs");
+        let output = run_sandboxed(
+            json!({"tmpfs_paths": [dir]}),
+            &format!("touch {0}/file && ls {0}", dir.display()),
+        )
+        .await;
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "file\n");
+        assert!(!dir.join("file").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn seccomp_blocks_namespace_escapes() {
+        if !user_namespaces_available() {
+            return;
+        }
+        let output = run_sandboxed(json!({}), "unshare -U true").await;
+        assert!(!output.status.success());
+
+        let output = run_sandboxed(json!({"seccomp": false}), "unshare -U true").await;
+        assert!(output.status.success(), "{:?}", output);
+    }
+
+    #[tokio::test]
+    async fn seccomp_blocks_clone_into_new_namespaces() {
+        if !user_namespaces_available() {
+            return;
+        }
+        // Prints the errno of `clone` with `flags`, the child exits right away
+        let clone = |flags: libc::c_int| {
+            format!(
+                "perl -e '$pid = syscall({}, {}, 0, 0, 0, 0); exit 0 if $pid == 0; print $pid < 0 ? $!+0 : 0'",
+                libc::SYS_clone,
+                flags | libc::SIGCHLD
+            )
+        };
+
+        let output = run_sandboxed(json!({}), &clone(libc::CLONE_NEWUSER)).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            libc::EPERM.to_string()
+        );
+        let output = run_sandboxed(json!({}), &clone(0)).await;
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "0");
+        let output = run_sandboxed(json!({"seccomp": false}), &clone(libc::CLONE_NEWUSER)).await;
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "0");
+
+        let clone3 = format!("perl -e 'syscall({}, 0, 0); print $!+0'", libc::SYS_clone3);
+        let output = run_sandboxed(json!({}), &clone3).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            libc::ENOSYS.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn resource_limits_are_applied() {
+        if !user_namespaces_available() {
+            return;
+        }
+        let output = run_sandboxed(json!({"limits": {"file_size_bytes": 1024}}), "ulimit -f").await;
+        // `ulimit -f` reports 512-byte blocks
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+    }
+}
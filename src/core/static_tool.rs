@@ -0,0 +1,376 @@
+use crate::core::config::{ErrorMode, StaticMetadata, StaticRule};
+use crate::core::output;
+use crate::core::template::{Template, TemplateMode};
+use duration_string::DurationString;
+use rmcp::ErrorData;
+use rmcp::model::{CallToolResult, Content, ErrorCode, JsonObject};
+use serde_json::Value;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// The reply a static tool picked for an input, with its templates rendered.
+#[derive(Debug, Clone)]
+pub struct RenderedReply {
+    /// Index of the matching rule, `None` for the default reply.
+    pub rule: Option<usize>,
+    pub latency: Duration,
+    pub response: Result<String, String>,
+}
+
+struct Reply {
+    when: Option<JsonObject>,
+    response_template: Option<String>,
+    error_template: Option<String>,
+    latency: Option<Duration>,
+}
+
+pub struct StaticTool {
+    template: Template,
+    rules: Vec<Reply>,
+    default: Reply,
+    error_mode: ErrorMode,
+}
+
+impl StaticTool {
+    const NO_MATCH_MESSAGE: &'static str = "No rule matches the input";
+
+    fn response_template_name(rule: Option<usize>) -> String {
+        match rule {
+            Some(idx) => format!("response_{}", idx),
+            None => "response".to_string(),
+        }
+    }
+
+    fn error_template_name(rule: Option<usize>) -> String {
+        match rule {
+            Some(idx) => format!("error_{}", idx),
+            None => "error".to_string(),
+        }
+    }
+
+    pub fn new(
+        tool_index: usize,
+        static_metadata: StaticMetadata,
+        error_mode: ErrorMode,
+        template_mode: TemplateMode,
+    ) -> Self {
+        let StaticMetadata {
+            rules,
+            response,
+            error,
+            latency,
+            ..
+        } = static_metadata;
+
+        let mut template = Template::new(template_mode);
+        let default = StaticRule {
+            when: None,
+            response,
+            error,
+            latency,
+        };
+        let default = Self::reply(tool_index, None, default, &mut template);
+        let rules = rules
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, rule)| {
+                if rule.response.is_none() && rule.error.is_none() {
+                    panic!(
+                        "Static rule {} sets neither response nor error, tool index {}",
+                        idx, tool_index
+                    );
+                }
+                Self::reply(tool_index, Some(idx), rule, &mut template)
+            })
+            .collect();
+
+        Self {
+            template,
+            rules,
+            default,
+            error_mode,
+        }
+    }
+
+    /// Registers the templates of a rule, or of the default reply when `rule` is `None`.
+    fn reply(
+        tool_index: usize,
+        rule: Option<usize>,
+        static_rule: StaticRule,
+        template: &mut Template,
+    ) -> Reply {
+        let StaticRule {
+            when,
+            response,
+            error,
+            latency,
+        } = static_rule;
+
+        if response.is_some() && error.is_some() {
+            panic!(
+                "Static reply sets both response and error, tool index {}",
+                tool_index
+            );
+        }
+
+        let mut register = |name: String, text: Option<String>| {
+            text.map(|text| {
+                template.add_template(&name, &text).unwrap_or_else(|err| {
+                    panic!(
+                        "Error registering {} template, tool index {}: {}",
+                        name, tool_index, err
+                    )
+                });
+                name
+            })
+        };
+        let response_template = register(Self::response_template_name(rule), response);
+        let error_template = register(Self::error_template_name(rule), error);
+
+        let latency = latency.map(|latency| {
+            DurationString::from_string(latency.clone())
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "Invalid static latency {}, tool index {}: {}",
+                        latency, tool_index, err
+                    )
+                })
+                .into()
+        });
+
+        Reply {
+            when,
+            response_template,
+            error_template,
+            latency,
+        }
+    }
+
+    /// A rule matches when every field of its `when` equals the same field of the input.
+    fn matches(reply: &Reply, input: &Value) -> bool {
+        reply
+            .when
+            .iter()
+            .flatten()
+            .all(|(name, expected)| input.get(name).is_some_and(|value| value == expected))
+    }
+
+    pub fn render(&self, context: &Value) -> Result<RenderedReply, ErrorData> {
+        let input = context.get(Template::INPUT_NAME).unwrap_or(&Value::Null);
+        let (rule, reply) = match self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, reply)| Self::matches(reply, input))
+        {
+            Some((idx, reply)) => (Some(idx), reply),
+            None => (None, &self.default),
+        };
+
+        let render = |name: &str| {
+            self.template.render(name, context).map_err(|err| {
+                ErrorData::new(
+                    ErrorCode::PARSE_ERROR,
+                    format!("Error while rendering {} template: {}", name, err),
+                    None,
+                )
+            })
+        };
+        let response = match (&reply.response_template, &reply.error_template) {
+            (Some(name), _) => Ok(render(name)?),
+            (None, Some(name)) => Err(render(name)?),
+            (None, None) => Err(Self::NO_MATCH_MESSAGE.to_string()),
+        };
+
+        Ok(RenderedReply {
+            rule,
+            latency: reply.latency.or(self.default.latency).unwrap_or_default(),
+            response,
+        })
+    }
+
+    /// Describes the reply a call would get, for `easymcp render`.
+    pub fn preview(&self, rendered: &RenderedReply) -> String {
+        let mut lines = vec![match rendered.rule {
+            Some(idx) => format!("Rule: {}", idx + 1),
+            None => "Rule: default".to_string(),
+        }];
+        if !rendered.latency.is_zero() {
+            lines.push(format!(
+                "Latency: {}",
+                DurationString::from(rendered.latency)
+            ));
+        }
+        match rendered.response {
+            Ok(ref response) => lines.push(format!("Response:\n{}", response)),
+            Err(ref error) => lines.push(format!("Error: {}", error)),
+        }
+        lines.join("\n")
+    }
+
+    /// Builds the result of a reply, as JSON content when the response is valid JSON.
+    pub fn result(&self, rendered: RenderedReply) -> Result<CallToolResult, ErrorData> {
+        match rendered.response {
+            Ok(response) => {
+                let content = match serde_json::from_str::<Value>(&response) {
+                    Ok(json_output) => output::json_content(json_output)?,
+                    Err(_) => Content::text(response),
+                };
+                Ok(CallToolResult::success(vec![content]))
+            }
+            Err(error) => output::execution_error(&self.error_mode, error),
+        }
+    }
+
+    /// Answers a call after the simulated latency, which `ct` cuts short.
+    pub async fn call(
+        &self,
+        context: &Value,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, ErrorData> {
+        let rendered = self.render(context)?;
+
+        if !rendered.latency.is_zero() {
+            tokio::select! {
+                _ = tokio::time::sleep(rendered.latency) => {}
+                _ = ct.cancelled() => {
+                    return Err(ErrorData::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        "Static response was cancelled".to_string(),
+                        None,
+                    ));
+                }
+            }
+        }
+
+        self.result(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn static_tool(static_metadata: Value, error_mode: ErrorMode) -> StaticTool {
+        let mut metadata = json!({"input_schema": {}});
+        if let Value::Object(extra) = static_metadata {
+            metadata.as_object_mut().unwrap().extend(extra);
+        }
+        StaticTool::new(
+            0,
+            serde_json::from_value(metadata).unwrap(),
+            error_mode,
+            TemplateMode::FULL,
+        )
+    }
+
+    fn weather() -> StaticTool {
+        static_tool(
+            json!({
+                "rules": [
+                    {"when": {"city": "Paris"}, "response": "{\"city\": \"Paris\", \"temp\": 18}"},
+                    {"when": {"city": "Paris", "unit": "F"}, "response": "unreachable"},
+                    {"when": {"city": "Atlantis"}, "error": "Unknown city {{ input.city }}"},
+                ],
+                "response": "No forecast for {{ input.city }}",
+            }),
+            ErrorMode::RESULT,
+        )
+    }
+
+    async fn call_input(tool: &StaticTool, input: Value) -> CallToolResult {
+        let context = json!({"input": input});
+        tool.call(&context, CancellationToken::new()).await.unwrap()
+    }
+
+    fn text(result: &CallToolResult) -> String {
+        let content = &result.content.as_ref().unwrap()[0];
+        content.as_text().unwrap().text.clone()
+    }
+
+    #[tokio::test]
+    async fn the_first_matching_rule_answers() {
+        let tool = weather();
+        let rendered = tool
+            .render(&json!({"input": {"city": "Paris", "unit": "F"}}))
+            .unwrap();
+        assert_eq!(rendered.rule, Some(0));
+
+        let result = call_input(&tool, json!({"city": "Paris", "unit": "F"})).await;
+        assert_ne!(result.is_error, Some(true));
+        let json: Value = serde_json::from_str(&text(&result)).unwrap();
+        assert_eq!(json, json!({"city": "Paris", "temp": 18}));
+    }
+
+    #[tokio::test]
+    async fn unmatched_inputs_get_the_default_reply() {
+        let tool = weather();
+        let result = call_input(&tool, json!({"city": "Lyon"})).await;
+        assert_ne!(result.is_error, Some(true));
+        assert_eq!(text(&result), "No forecast for Lyon");
+        assert_eq!(
+            tool.preview(&tool.render(&json!({"input": {"city": "Lyon"}})).unwrap()),
+            "Rule: default\nResponse:\nNo forecast for Lyon"
+        );
+    }
+
+    #[tokio::test]
+    async fn error_rules_follow_the_error_mode() {
+        let result = call_input(&weather(), json!({"city": "Atlantis"})).await;
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(text(&result), "Unknown city Atlantis");
+
+        let tool = static_tool(
+            json!({"rules": [{"when": {"city": "Paris"}, "response": "sunny"}]}),
+            ErrorMode::PROTOCOL,
+        );
+        let context = json!({"input": {"city": "Lyon"}});
+        let err = tool
+            .call(&context, CancellationToken::new())
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, StaticTool::NO_MATCH_MESSAGE);
+    }
+
+    #[tokio::test]
+    async fn latency_delays_the_reply_until_cancelled() {
+        let tool = static_tool(
+            json!({
+                "rules": [{"when": {"slow": true}, "response": "late", "latency": "10s"}],
+                "response": "fast",
+                "latency": "10ms",
+            }),
+            ErrorMode::PROTOCOL,
+        );
+        let context = json!({"input": {"slow": false}});
+        let rendered = tool.render(&context).unwrap();
+        assert_eq!(rendered.latency, Duration::from_millis(10));
+        let result = tool.call(&context, CancellationToken::new()).await.unwrap();
+        assert_eq!(text(&result), "fast");
+
+        let ct = CancellationToken::new();
+        let cancel = ct.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+        });
+        let started = std::time::Instant::now();
+        let err = tool
+            .call(&json!({"input": {"slow": true}}), ct)
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "Static response was cancelled");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    #[should_panic(expected = "sets both response and error")]
+    fn rules_cannot_set_both_response_and_error() {
+        static_tool(
+            json!({"rules": [{"when": {}, "response": "ok", "error": "no"}]}),
+            ErrorMode::PROTOCOL,
+        );
+    }
+}
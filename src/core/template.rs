@@ -1,32 +1,1145 @@
-use serde_json::Value;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::Write;
-use tinytemplate::TinyTemplate;
-use tinytemplate::error::Error;
+use std::sync::Arc;
 
-pub struct Template<'a> {
-    template: TinyTemplate<'a>,
-    templates: HashMap<&'a str, &'a str>,
+/// Syntax of the templates of a tool.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum TemplateMode {
+    /// `{ input.x }` and `{ input.x | formatter }` placeholders, any other brace is literal text
+    #[default]
+    #[serde(alias = "simple")]
+    SIMPLE,
+    /// Jinja-like `{{ expression | filter }}` values with `{% if %}` and `{% for %}` blocks
+    #[serde(alias = "full")]
+    FULL,
 }
 
-// Manually implement Send and Sync for Template
-unsafe impl<'a> Send for Template<'a> {}
-unsafe impl<'a> Sync for Template<'a> {}
+/// Error raised while compiling or rendering a template.
+#[derive(Debug)]
+pub enum TemplateError {
+    Parse(String),
+    UnknownTemplate(String),
+    NotFound(String),
+    UnknownFormatter(String),
+    Format(String),
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::Parse(message) => write!(f, "Invalid template: {}", message),
+            TemplateError::UnknownTemplate(name) => write!(f, "Unknown template '{}'", name),
+            TemplateError::NotFound(path) => write!(f, "Failed to find value '{}'", path),
+            TemplateError::UnknownFormatter(name) => write!(f, "Unknown formatter '{}'", name),
+            TemplateError::Format(message) => {
+                write!(f, "Error while formatting value: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl From<serde_json::Error> for TemplateError {
+    fn from(err: serde_json::Error) -> Self {
+        TemplateError::Format(err.to_string())
+    }
+}
+
+impl From<std::fmt::Error> for TemplateError {
+    fn from(err: std::fmt::Error) -> Self {
+        TemplateError::Format(err.to_string())
+    }
+}
+
+type Formatter = fn(&Value, &mut String) -> Result<(), TemplateError>;
+
+/// One step of a dotted path. Numeric steps also index into arrays.
+enum PathStep {
+    Name(String),
+    Index(String, usize),
+}
+
+/// A dotted path into the template context, such as `input.tags.0`.
+struct Path {
+    source: String,
+    steps: Vec<PathStep>,
+}
+
+impl Path {
+    fn parse(text: &str) -> Result<Self, TemplateError> {
+        let invalid = |step: &str| step.is_empty() || step.contains(char::is_whitespace);
+        if text.split('.').any(invalid) {
+            return Err(TemplateError::Parse(format!("invalid path '{}'", text)));
+        }
+
+        let steps = text
+            .split('.')
+            .map(|step| match step.parse::<usize>() {
+                Ok(index) => PathStep::Index(step.to_string(), index),
+                Err(_) => PathStep::Name(step.to_string()),
+            })
+            .collect();
+
+        Ok(Self {
+            source: text.to_string(),
+            steps,
+        })
+    }
+
+    fn root(&self) -> &str {
+        match self.steps[0] {
+            PathStep::Name(ref name) | PathStep::Index(ref name, _) => name,
+        }
+    }
+
+    /// Follows the path from `value`, skipping its first `skip` steps.
+    fn lookup_from<'v>(&self, value: &'v Value, skip: usize) -> Option<&'v Value> {
+        self.steps
+            .iter()
+            .skip(skip)
+            .try_fold(value, |value, step| match (step, value) {
+                (PathStep::Index(_, index), Value::Array(items)) => items.get(*index),
+                (PathStep::Name(name) | PathStep::Index(name, _), Value::Object(fields)) => {
+                    fields.get(name)
+                }
+                _ => None,
+            })
+    }
+}
+
+/// Reads the pieces of a `SIMPLE` placeholder from the start of a text.
+struct Scanner<'t> {
+    text: &'t str,
+    pos: usize,
+}
+
+impl<'t> Scanner<'t> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.pos += expected.len_utf8();
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    /// Reads a run of letters, digits and underscores.
+    fn word(&mut self) -> Option<&'t str> {
+        let start = self.pos;
+        while let Some(c) = self.peek().filter(|c| c.is_alphanumeric() || *c == '_') {
+            self.pos += c.len_utf8();
+        }
+        (self.pos > start).then(|| &self.text[start..self.pos])
+    }
+}
+
+/// A `{ path }` or `{ path | formatter }` placeholder of a `SIMPLE` template.
+struct Placeholder {
+    path: Path,
+    formatter: Option<String>,
+    format: Formatter,
+}
+
+impl Placeholder {
+    /// Scans the placeholder opened by the brace at the start of `text`.
+    ///
+    /// Returns its length, path and formatter name, or `None` when the brace is literal text:
+    /// a placeholder is a dotted path under one of [`Template::ROOT_NAMES`], such as
+    /// `input.location.lat` or `input.tags.0`, optionally followed by `| formatter`.
+    fn scan(text: &str) -> Option<(usize, &str, Option<&str>)> {
+        let mut scanner = Scanner { text, pos: 0 };
+        if !scanner.eat('{') {
+            return None;
+        }
+        scanner.skip_whitespace();
+
+        let path_start = scanner.pos;
+        let root = scanner.word()?;
+        if !Template::ROOT_NAMES.contains(&root) || !scanner.eat('.') {
+            return None;
+        }
+        scanner.word()?;
+        while scanner.eat('.') {
+            scanner.word()?;
+        }
+        let path = &text[path_start..scanner.pos];
+        scanner.skip_whitespace();
+
+        let formatter = if scanner.eat('|') {
+            scanner.skip_whitespace();
+            let formatter = scanner.word()?;
+            scanner.skip_whitespace();
+            Some(formatter)
+        } else {
+            None
+        };
+
+        scanner.eat('}').then_some((scanner.pos, path, formatter))
+    }
+
+    fn new(path: &str, formatter: Option<&str>) -> Result<Self, TemplateError> {
+        let format = match formatter {
+            Some(name) => Template::formatter(name)
+                .ok_or_else(|| TemplateError::UnknownFormatter(name.to_string()))?,
+            None => Template::default_formatter,
+        };
+
+        Ok(Self {
+            path: Path::parse(path)?,
+            formatter: formatter.map(str::to_string),
+            format,
+        })
+    }
+
+    fn is_shell_escaped(&self) -> bool {
+        self.formatter.as_deref() == Some(Template::SHELL_ESCAPE_FORMATTER)
+    }
+
+    fn render(&self, context: &Value, output: &mut String) -> Result<(), TemplateError> {
+        let value = self
+            .path
+            .lookup_from(context, 0)
+            .ok_or_else(|| TemplateError::NotFound(self.path.source.clone()))?;
+        (self.format)(value, output)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Pipe,
+    Comma,
+    OpenParen,
+    CloseParen,
+    Equal,
+    NotEqual,
+}
+
+impl Token {
+    /// Splits the inside of a `{{ }}` or `{% %}` tag into tokens.
+    fn tokenize(text: &str) -> Result<Vec<Token>, TemplateError> {
+        let mut tokens = Vec::new();
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            let token = match c {
+                c if c.is_whitespace() => continue,
+                '|' => Token::Pipe,
+                ',' => Token::Comma,
+                '(' => Token::OpenParen,
+                ')' => Token::CloseParen,
+                '=' | '!' if chars.next_if(|&(_, next)| next == '=').is_some() => {
+                    if c == '=' {
+                        Token::Equal
+                    } else {
+                        Token::NotEqual
+                    }
+                }
+                '"' | '\'' => {
+                    let mut literal = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, 'n')) => literal.push('\n'),
+                                Some((_, 't')) => literal.push('\t'),
+                                Some((_, escaped)) => literal.push(escaped),
+                                None => break,
+                            },
+                            Some((_, quote)) if quote == c => break,
+                            Some((_, other)) => literal.push(other),
+                            None => {
+                                return Err(TemplateError::Parse(format!(
+                                    "unterminated string in '{}'",
+                                    text
+                                )));
+                            }
+                        }
+                    }
+                    Token::Literal(Value::String(literal))
+                }
+                c if c.is_alphanumeric() || c == '_' || c == '-' => {
+                    let mut end = start + c.len_utf8();
+                    while let Some((index, next)) = chars.next_if(|&(_, next)| {
+                        next.is_alphanumeric() || matches!(next, '_' | '.' | '-' | '+')
+                    }) {
+                        end = index + next.len_utf8();
+                    }
+                    let word = &text[start..end];
+                    match word {
+                        "true" => Token::Literal(Value::Bool(true)),
+                        "false" => Token::Literal(Value::Bool(false)),
+                        "null" => Token::Literal(Value::Null),
+                        _ if c.is_ascii_digit() || c == '-' => {
+                            Token::Literal(Value::Number(word.parse().map_err(|_| {
+                                TemplateError::Parse(format!("invalid number '{}'", word))
+                            })?))
+                        }
+                        _ => Token::Ident(word.to_string()),
+                    }
+                }
+                other => {
+                    return Err(TemplateError::Parse(format!(
+                        "unexpected '{}' in '{}'",
+                        other, text
+                    )));
+                }
+            };
+            tokens.push(token);
+        }
+
+        Ok(tokens)
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "{}", ident),
+            Token::Literal(value) => write!(f, "{}", value),
+            Token::Pipe => write!(f, "|"),
+            Token::Comma => write!(f, ","),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::Equal => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+        }
+    }
+}
+
+/// A path or a literal.
+enum Operand {
+    Path(Path),
+    Literal(Value),
+}
+
+impl Operand {
+    fn eval<'a>(&'a self, scope: &'a Scope<'a>) -> Option<Cow<'a, Value>> {
+        match self {
+            Operand::Path(path) => scope.lookup(path).map(Cow::Borrowed),
+            Operand::Literal(value) => Some(Cow::Borrowed(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FilterKind {
+    Default,
+    Join,
+    Upper,
+    Lower,
+    Trim,
+    Json,
+    Base64,
+    Sha256,
+    Date,
+    UrlEncode,
+    ShellEscape,
+}
+
+impl FilterKind {
+    fn parse(name: &str) -> Option<Self> {
+        let kind = match name {
+            "default" => FilterKind::Default,
+            "join" => FilterKind::Join,
+            "upper" => FilterKind::Upper,
+            "lower" => FilterKind::Lower,
+            "trim" => FilterKind::Trim,
+            "json" => FilterKind::Json,
+            "base64" => FilterKind::Base64,
+            "sha256" => FilterKind::Sha256,
+            "date" => FilterKind::Date,
+            "url_encode" => FilterKind::UrlEncode,
+            Template::SHELL_ESCAPE_FORMATTER => FilterKind::ShellEscape,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    /// Minimum and maximum number of arguments.
+    fn arity(&self) -> (usize, usize) {
+        match self {
+            FilterKind::Default => (1, 1),
+            FilterKind::Join | FilterKind::Date => (0, 1),
+            _ => (0, 0),
+        }
+    }
+}
+
+/// A `| name` or `| name(arguments)` filter.
+struct Filter {
+    kind: FilterKind,
+    args: Vec<Operand>,
+}
+
+impl Filter {
+    /// Applies the filter. Undefined values are passed through, except by `default`.
+    fn apply<'a>(
+        &'a self,
+        value: Option<Cow<'a, Value>>,
+        scope: &'a Scope<'a>,
+    ) -> Result<Option<Cow<'a, Value>>, TemplateError> {
+        if self.kind == FilterKind::Default {
+            return Ok(match value {
+                Some(value) if !value.is_null() => Some(value),
+                _ => self.args[0].eval(scope),
+            });
+        }
+        let Some(value) = value else {
+            return Ok(None);
+        };
+
+        let arg = |index: usize| -> Option<Cow<'a, str>> {
+            self.args
+                .get(index)
+                .and_then(|arg| arg.eval(scope))
+                .map(|arg| Cow::Owned(Template::text(&arg).into_owned()))
+        };
+
+        let text = Template::text(&value);
+        let result = match self.kind {
+            FilterKind::Join => {
+                let separator = arg(0).unwrap_or_default();
+                match value.as_ref() {
+                    Value::Array(items) => items
+                        .iter()
+                        .map(|item| Template::text(item))
+                        .collect::<Vec<_>>()
+                        .join(&separator),
+                    _ => text.into_owned(),
+                }
+            }
+            FilterKind::Upper => text.to_uppercase(),
+            FilterKind::Lower => text.to_lowercase(),
+            FilterKind::Trim => text.trim().to_string(),
+            FilterKind::Json => serde_json::to_string(value.as_ref())?,
+            FilterKind::Base64 => BASE64_STANDARD.encode(text.as_bytes()),
+            FilterKind::Sha256 => format!("{:x}", Sha256::digest(text.as_bytes())),
+            FilterKind::Date => Self::date(&value, arg(0).as_deref())?,
+            FilterKind::UrlEncode => urlencoding::encode(&text).into_owned(),
+            FilterKind::ShellEscape => Template::shell_quote(&text),
+            FilterKind::Default => unreachable!(),
+        };
+
+        Ok(Some(Cow::Owned(Value::String(result))))
+    }
+
+    /// Formats a unix timestamp in seconds or an RFC 3339 string, as RFC 3339 in UTC by default.
+    fn date(value: &Value, format: Option<&str>) -> Result<String, TemplateError> {
+        let date_time = match value {
+            Value::Number(seconds) => seconds.as_f64().and_then(|seconds| {
+                DateTime::from_timestamp(
+                    seconds.floor() as i64,
+                    (seconds.fract() * 1e9).round() as u32,
+                )
+            }),
+            Value::String(text) => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|date_time| date_time.with_timezone(&Utc)),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            TemplateError::Format(format!(
+                "date expects a unix timestamp or an RFC 3339 string, got {}",
+                value
+            ))
+        })?;
+
+        match format {
+            Some(format) => {
+                let mut output = String::new();
+                write!(output, "{}", date_time.format(format))?;
+                Ok(output)
+            }
+            None => Ok(date_time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        }
+    }
+}
+
+/// A value with its filters, e.g. `input.tags | join(", ") | upper`.
+struct Expr {
+    source: String,
+    operand: Operand,
+    filters: Vec<Filter>,
+}
+
+impl Expr {
+    fn eval<'a>(&'a self, scope: &'a Scope<'a>) -> Result<Option<Cow<'a, Value>>, TemplateError> {
+        let mut value = self.operand.eval(scope);
+        for filter in self.filters.iter() {
+            value = filter.apply(value, scope)?;
+        }
+        Ok(value)
+    }
+
+    fn undefined(&self) -> TemplateError {
+        match self.operand {
+            Operand::Path(ref path) => TemplateError::NotFound(path.source.clone()),
+            Operand::Literal(_) => TemplateError::NotFound(self.source.clone()),
+        }
+    }
+
+    fn is_shell_escaped(&self) -> bool {
+        self.filters
+            .last()
+            .is_some_and(|filter| filter.kind == FilterKind::ShellEscape)
+    }
+}
+
+/// `[not] expression [== expression]`, also with `!=`.
+struct Condition {
+    negated: bool,
+    expr: Expr,
+    comparison: Option<(bool, Expr)>,
+}
+
+impl Condition {
+    fn eval(&self, scope: &Scope) -> Result<bool, TemplateError> {
+        let value = self.expr.eval(scope)?;
+        let result = match self.comparison {
+            Some((equal, ref other)) => {
+                let other = other.eval(scope)?;
+                let value = value.as_deref().unwrap_or(&Value::Null);
+                let other = other.as_deref().unwrap_or(&Value::Null);
+                (value == other) == equal
+            }
+            None => value.is_some_and(|value| Self::truthy(&value)),
+        };
+        Ok(result != self.negated)
+    }
+
+    /// Null, false, zero and empty strings, arrays and objects are false.
+    fn truthy(value: &Value) -> bool {
+        match value {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+            Value::String(text) => !text.is_empty(),
+            Value::Array(items) => !items.is_empty(),
+            Value::Object(fields) => !fields.is_empty(),
+        }
+    }
+}
+
+/// Parses the tokens of a single tag.
+struct ExprParser<'t> {
+    source: &'t str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl<'t> ExprParser<'t> {
+    fn new(source: &'t str) -> Result<Self, TemplateError> {
+        Ok(Self {
+            source: source.trim(),
+            tokens: Token::tokenize(source)?,
+            position: 0,
+        })
+    }
+
+    fn error(&self, message: &str) -> TemplateError {
+        TemplateError::Parse(format!("{} in '{}'", message, self.source))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_if(&mut self, token: &Token) -> bool {
+        let matched = self.peek() == Some(token);
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.next_if(&Token::Ident(keyword.to_string()))
+    }
+
+    fn ident(&mut self) -> Result<String, TemplateError> {
+        match self.next() {
+            Some(Token::Ident(ident)) => Ok(ident),
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    fn end(&self) -> Result<(), TemplateError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(self.error(&format!("unexpected '{}'", token))),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, TemplateError> {
+        match self.next() {
+            Some(Token::Ident(path)) => Ok(Operand::Path(Path::parse(&path)?)),
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, TemplateError> {
+        let start = self.position;
+        let operand = self.operand()?;
+
+        let mut filters = Vec::new();
+        while self.next_if(&Token::Pipe) {
+            let name = self.ident()?;
+            let kind = FilterKind::parse(&name)
+                .ok_or_else(|| self.error(&format!("unknown filter '{}'", name)))?;
+
+            let mut args = Vec::new();
+            if self.next_if(&Token::OpenParen) && !self.next_if(&Token::CloseParen) {
+                loop {
+                    args.push(self.operand()?);
+                    if self.next_if(&Token::CloseParen) {
+                        break;
+                    }
+                    if !self.next_if(&Token::Comma) {
+                        return Err(self.error("expected ',' or ')'"));
+                    }
+                }
+            }
+
+            let (min, max) = kind.arity();
+            if args.len() < min || args.len() > max {
+                return Err(self.error(&format!(
+                    "filter '{}' takes {} argument(s), got {}",
+                    name,
+                    if min == max {
+                        min.to_string()
+                    } else {
+                        format!("{} to {}", min, max)
+                    },
+                    args.len()
+                )));
+            }
+            if kind == FilterKind::Date
+                && let Some(Operand::Literal(Value::String(format))) = args.first()
+                && StrftimeItems::new(format).any(|item| item == Item::Error)
+            {
+                return Err(self.error(&format!("invalid date format '{}'", format)));
+            }
+
+            filters.push(Filter { kind, args });
+        }
 
-impl<'a> Clone for Template<'a> {
-    fn clone(&self) -> Self {
-        let mut new_template = Template::new();
-        // Re-register all templates from the original
-        for (name, template_str) in &self.templates {
-            new_template
-                .add_template(name, template_str)
-                .expect("Failed to clone template");
+        Ok(Expr {
+            source: self.source_of(start),
+            operand,
+            filters,
+        })
+    }
+
+    /// Rebuilds the text of the tokens consumed since `start`, for messages.
+    fn source_of(&self, start: usize) -> String {
+        if start == 0 && self.position >= self.tokens.len() {
+            return self.source.to_string();
         }
-        new_template
+        self.tokens[start..self.position.min(self.tokens.len())]
+            .iter()
+            .map(Token::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn condition(&mut self) -> Result<Condition, TemplateError> {
+        let negated = self.keyword("not");
+        let expr = self.expr()?;
+        let comparison = match self.peek() {
+            Some(Token::Equal) | Some(Token::NotEqual) => {
+                let equal = self.next() == Some(Token::Equal);
+                Some((equal, self.expr()?))
+            }
+            _ => None,
+        };
+        self.end()?;
+
+        Ok(Condition {
+            negated,
+            expr,
+            comparison,
+        })
     }
 }
 
-impl<'a> Template<'a> {
+/// Keyword and arguments of a `{% keyword arguments %}` tag.
+type BlockTag<'t> = (&'t str, &'t str);
+
+enum Tag<'t> {
+    Text(&'t str),
+    Output(&'t str),
+    Block(&'t str),
+}
+
+enum Node {
+    Literal(String),
+    Placeholder(Placeholder),
+    Output(Expr),
+    If {
+        branches: Vec<(Condition, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        name: String,
+        iterable: Expr,
+        body: Vec<Node>,
+    },
+}
+
+/// Template context with the variables of the enclosing `for` loops.
+struct Scope<'a> {
+    context: &'a Value,
+    locals: Vec<(String, Value)>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, path: &Path) -> Option<&Value> {
+        match self
+            .locals
+            .iter()
+            .rev()
+            .find(|(name, _)| name == path.root())
+        {
+            Some((_, value)) => path.lookup_from(value, 1),
+            None => path.lookup_from(self.context, 0),
+        }
+    }
+}
+
+/// A template parsed once into literal text, values and blocks.
+struct CompiledTemplate {
+    nodes: Vec<Node>,
+    literal_len: usize,
+}
+
+impl CompiledTemplate {
+    fn new(nodes: Vec<Node>) -> Self {
+        let literal_len = nodes
+            .iter()
+            .map(|node| match node {
+                Node::Literal(text) => text.len(),
+                _ => 0,
+            })
+            .sum();
+
+        Self { nodes, literal_len }
+    }
+
+    /// Parses the `SIMPLE` syntax: `{ path }` and `{ path | formatter }` placeholders.
+    ///
+    /// Any other brace is literal text, and `\{` before a placeholder writes it literally.
+    fn compile_simple(text: &str) -> Result<Self, TemplateError> {
+        let mut nodes = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+
+        while let Some(start) = rest.find('{') {
+            let (before, after) = rest.split_at(start);
+            let Some((len, path, formatter)) = Placeholder::scan(after) else {
+                literal.push_str(before);
+                literal.push('{');
+                rest = &after[1..];
+                continue;
+            };
+            rest = &after[len..];
+
+            if let Some(before) = before.strip_suffix('\\') {
+                literal.push_str(before);
+                literal.push_str(&after[..len]);
+                continue;
+            }
+            literal.push_str(before);
+
+            if !literal.is_empty() {
+                nodes.push(Node::Literal(std::mem::take(&mut literal)));
+            }
+            nodes.push(Node::Placeholder(Placeholder::new(path, formatter)?));
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            nodes.push(Node::Literal(literal));
+        }
+
+        Ok(Self::new(nodes))
+    }
+
+    /// Parses the `FULL` syntax: `{{ expression }}` values, `{% block %}` tags and
+    /// `{# comments #}`. Single braces are literal text.
+    fn compile_full(text: &str) -> Result<Self, TemplateError> {
+        let mut tags = Self::tags(text)?.into_iter();
+        let (nodes, _) = Self::parse_nodes(&mut tags, &[])?;
+        Ok(Self::new(nodes))
+    }
+
+    fn tags(text: &str) -> Result<Vec<Tag<'_>>, TemplateError> {
+        let mut tags = Vec::new();
+        let mut rest = text;
+        let mut literal_start = 0;
+
+        while let Some(start) = rest[literal_start..].find('{').map(|i| i + literal_start) {
+            let close = match rest[start + 1..].chars().next() {
+                Some('{') => "}}",
+                Some('%') => "%}",
+                Some('#') => "#}",
+                _ => {
+                    literal_start = start + 1;
+                    continue;
+                }
+            };
+            let Some(end) = rest[start + 2..].find(close).map(|i| i + start + 2) else {
+                return Err(TemplateError::Parse(format!(
+                    "missing '{}' after byte {}",
+                    close,
+                    text.len() - rest.len() + start
+                )));
+            };
+
+            if start > 0 {
+                tags.push(Tag::Text(&rest[..start]));
+            }
+            let inner = &rest[start + 2..end];
+            match close {
+                "}}" => tags.push(Tag::Output(inner)),
+                "%}" => tags.push(Tag::Block(inner)),
+                _ => {}
+            }
+            rest = &rest[end + 2..];
+            literal_start = 0;
+        }
+        if !rest.is_empty() {
+            tags.push(Tag::Text(rest));
+        }
+
+        Ok(tags)
+    }
+
+    /// Parses nodes until one of the `terminators` block tags, which is returned with its arguments.
+    fn parse_nodes<'t>(
+        tags: &mut impl Iterator<Item = Tag<'t>>,
+        terminators: &[&str],
+    ) -> Result<(Vec<Node>, Option<BlockTag<'t>>), TemplateError> {
+        let mut nodes = Vec::new();
+
+        while let Some(tag) = tags.next() {
+            let block = match tag {
+                Tag::Text(text) => {
+                    nodes.push(Node::Literal(text.to_string()));
+                    continue;
+                }
+                Tag::Output(source) => {
+                    let mut parser = ExprParser::new(source)?;
+                    let expr = parser.expr()?;
+                    parser.end()?;
+                    nodes.push(Node::Output(expr));
+                    continue;
+                }
+                Tag::Block(block) => block.trim(),
+            };
+
+            let (keyword, arguments) = block.split_once(char::is_whitespace).unwrap_or((block, ""));
+            match keyword {
+                "if" => nodes.push(Self::parse_if(tags, arguments)?),
+                "for" => nodes.push(Self::parse_for(tags, arguments)?),
+                keyword if terminators.contains(&keyword) => {
+                    return Ok((nodes, Some((keyword, arguments))));
+                }
+                keyword => {
+                    return Err(TemplateError::Parse(format!(
+                        "unexpected '{{% {} %}}'",
+                        keyword
+                    )));
+                }
+            }
+        }
+
+        match terminators.last() {
+            Some(terminator) => Err(TemplateError::Parse(format!(
+                "missing '{{% {} %}}'",
+                terminator
+            ))),
+            None => Ok((nodes, None)),
+        }
+    }
+
+    fn parse_if<'t>(
+        tags: &mut impl Iterator<Item = Tag<'t>>,
+        arguments: &str,
+    ) -> Result<Node, TemplateError> {
+        let mut branches = Vec::new();
+        let mut condition = ExprParser::new(arguments)?.condition()?;
+
+        loop {
+            let (body, terminator) = Self::parse_nodes(tags, &["elif", "else", "endif"])?;
+            branches.push((condition, body));
+
+            match terminator {
+                Some(("elif", arguments)) => {
+                    condition = ExprParser::new(arguments)?.condition()?;
+                }
+                Some(("else", _)) => {
+                    let (otherwise, _) = Self::parse_nodes(tags, &["endif"])?;
+                    return Ok(Node::If {
+                        branches,
+                        otherwise,
+                    });
+                }
+                _ => {
+                    return Ok(Node::If {
+                        branches,
+                        otherwise: vec![],
+                    });
+                }
+            }
+        }
+    }
+
+    fn parse_for<'t>(
+        tags: &mut impl Iterator<Item = Tag<'t>>,
+        arguments: &str,
+    ) -> Result<Node, TemplateError> {
+        let mut parser = ExprParser::new(arguments)?;
+        let name = parser.ident()?;
+        if name.contains('.') || !parser.keyword("in") {
+            return Err(parser.error("expected '<name> in <expression>'"));
+        }
+        let iterable = parser.expr()?;
+        parser.end()?;
+
+        let (body, _) = Self::parse_nodes(tags, &["endfor"])?;
+        Ok(Node::For {
+            name,
+            iterable,
+            body,
+        })
+    }
+
+    /// Appends `shell_escape` to every value that does not already end with it.
+    ///
+    /// `SIMPLE` placeholders with another formatter are kept as written.
+    fn shell_escape_outputs(nodes: &mut [Node]) {
+        for node in nodes.iter_mut() {
+            match node {
+                Node::Placeholder(placeholder) if placeholder.formatter.is_none() => {
+                    placeholder.formatter = Some(Template::SHELL_ESCAPE_FORMATTER.to_string());
+                    placeholder.format = Template::shell_escape_formatter;
+                }
+                Node::Output(expr) if !expr.is_shell_escaped() => expr.filters.push(Filter {
+                    kind: FilterKind::ShellEscape,
+                    args: vec![],
+                }),
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    for (_, body) in branches.iter_mut() {
+                        Self::shell_escape_outputs(body);
+                    }
+                    Self::shell_escape_outputs(otherwise);
+                }
+                Node::For { body, .. } => Self::shell_escape_outputs(body),
+                _ => {}
+            }
+        }
+    }
+
+    /// Collects the values that are not passed through `shell_escape`.
+    fn unescaped_outputs(nodes: &[Node], unescaped: &mut Vec<String>) {
+        for node in nodes.iter() {
+            match node {
+                Node::Placeholder(placeholder) if !placeholder.is_shell_escaped() => {
+                    unescaped.push(placeholder.path.source.clone())
+                }
+                Node::Output(expr) if !expr.is_shell_escaped() => {
+                    unescaped.push(expr.source.clone())
+                }
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    for (_, body) in branches.iter() {
+                        Self::unescaped_outputs(body, unescaped);
+                    }
+                    Self::unescaped_outputs(otherwise, unescaped);
+                }
+                Node::For { body, .. } => Self::unescaped_outputs(body, unescaped),
+                _ => {}
+            }
+        }
+    }
+
+    fn render(&self, context: &Value) -> Result<String, TemplateError> {
+        let mut output = String::with_capacity(self.literal_len + 16 * self.nodes.len());
+        let mut scope = Scope {
+            context,
+            locals: vec![],
+        };
+        Self::render_nodes(&self.nodes, &mut scope, &mut output)?;
+        Ok(output)
+    }
+
+    fn render_nodes(
+        nodes: &[Node],
+        scope: &mut Scope,
+        output: &mut String,
+    ) -> Result<(), TemplateError> {
+        for node in nodes.iter() {
+            match node {
+                Node::Literal(text) => output.push_str(text),
+                Node::Placeholder(placeholder) => placeholder.render(scope.context, output)?,
+                Node::Output(expr) => {
+                    let value = expr.eval(scope)?.ok_or_else(|| expr.undefined())?;
+                    output.push_str(&Template::text(&value));
+                }
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    let mut body = otherwise;
+                    for (condition, branch) in branches.iter() {
+                        if condition.eval(scope)? {
+                            body = branch;
+                            break;
+                        }
+                    }
+                    Self::render_nodes(body, scope, output)?;
+                }
+                Node::For {
+                    name,
+                    iterable,
+                    body,
+                } => {
+                    let items: Vec<Value> = match iterable.eval(scope)?.as_deref() {
+                        None | Some(Value::Null) => vec![],
+                        Some(Value::Array(items)) => items.clone(),
+                        Some(Value::Object(fields)) => fields
+                            .iter()
+                            .map(|(key, value)| json!({ "key": key, "value": value }))
+                            .collect(),
+                        Some(_) => {
+                            return Err(TemplateError::Format(format!(
+                                "'{}' is not a list",
+                                iterable.source
+                            )));
+                        }
+                    };
+
+                    let length = items.len();
+                    for (index, item) in items.into_iter().enumerate() {
+                        let loop_info = json!({
+                            "index": index + 1,
+                            "index0": index,
+                            "first": index == 0,
+                            "last": index + 1 == length,
+                            "length": length,
+                        });
+                        scope.locals.push((name.clone(), item));
+                        scope
+                            .locals
+                            .push((Template::LOOP_NAME.to_string(), loop_info));
+                        let rendered = Self::render_nodes(body, scope, output);
+                        scope.locals.truncate(scope.locals.len() - 2);
+                        rendered?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Named templates of a tool, compiled once when the tool is created.
+///
+/// Compiled templates are immutable and shared through `Arc`, so cloning is cheap
+/// and rendering never re-parses the template text.
+#[derive(Clone, Default)]
+pub struct Template {
+    mode: TemplateMode,
+    templates: HashMap<String, Arc<CompiledTemplate>>,
+}
+
+impl Template {
+    pub const INPUT_NAME: &'static str = "input";
+    pub const VARS_NAME: &'static str = "vars";
+    pub const ENV_NAME: &'static str = "env";
+    pub const SECRETS_NAME: &'static str = "secrets";
+    pub const NOW_NAME: &'static str = "now";
+    pub const REQUEST_NAME: &'static str = "request";
+    /// Top-level names of the template context
+    pub const ROOT_NAMES: [&'static str; 6] = [
+        Self::INPUT_NAME,
+        Self::VARS_NAME,
+        Self::ENV_NAME,
+        Self::SECRETS_NAME,
+        Self::NOW_NAME,
+        Self::REQUEST_NAME,
+    ];
+    pub const LOOP_NAME: &'static str = "loop";
+    pub const SHELL_ESCAPE_FORMATTER: &'static str = "shell_escape";
+
+    /// Returns the placeholders of a template text that are not passed through `shell_escape`.
+    pub fn unescaped_shell_placeholders(&self, template_text: &str) -> Vec<String> {
+        let mut unescaped = Vec::new();
+        if let Ok(compiled) = self.compile(template_text) {
+            CompiledTemplate::unescaped_outputs(&compiled.nodes, &mut unescaped);
+        }
+        unescaped
+    }
+
+    /// Writes a placeholder with the `shell_escape` formatter in the syntax of this template.
+    pub fn shell_escaped(&self, placeholder: &str) -> String {
+        match self.mode {
+            TemplateMode::SIMPLE => {
+                format!("{{ {} | {} }}", placeholder, Self::SHELL_ESCAPE_FORMATTER)
+            }
+            TemplateMode::FULL => {
+                format!(
+                    "{{{{ {} | {} }}}}",
+                    placeholder,
+                    Self::SHELL_ESCAPE_FORMATTER
+                )
+            }
+        }
+    }
+
+    /// Text of a value in a `FULL` template: strings as is, nothing for null and JSON otherwise.
+    fn text(value: &Value) -> Cow<'_, str> {
+        match value {
+            Value::String(text) => Cow::Borrowed(text),
+            Value::Null => Cow::Borrowed(""),
+            other => Cow::Owned(other.to_string()),
+        }
+    }
+
+    pub fn shell_quote(raw: &str) -> String {
+        format!("'{}'", raw.replace('\'', "'\\''"))
+    }
+
     /// Default formatter that converts JSON values to their string representation.
     ///
     /// This formatter serializes the JSON value to a string and removes
@@ -38,19 +1151,18 @@ impl<'a> Template<'a> {
     ///
     /// # Returns
     /// * `Ok(())` if formatting was successful
-    /// * `Err(Error)` if JSON serialization failed
-    fn default_formatter(value: &Value, output: &mut String) -> Result<(), Error> {
+    /// * `Err(TemplateError)` if JSON serialization failed
+    fn default_formatter(value: &Value, output: &mut String) -> Result<(), TemplateError> {
         let object_string = serde_json::to_string(value)?;
         let object_string = object_string.trim_end_matches('"').trim_start_matches('"');
-        output.write_str(&object_string)?;
+        output.write_str(object_string)?;
         Ok(())
     }
 
     /// URL-encodes a JSON value for safe use in URLs and query parameters.
     ///
-    /// This formatter converts the JSON value to its string representation,
-    /// removes surrounding quotes, and then URL-encodes the result using
-    /// the `urlencoding` crate. This is useful for including data in HTTP
+    /// Strings are encoded as is and other values as their JSON representation,
+    /// using the `urlencoding` crate. This is useful for including data in HTTP
     /// requests that might contain special characters.
     ///
     /// # Arguments
@@ -59,39 +1171,416 @@ impl<'a> Template<'a> {
     ///
     /// # Returns
     /// * `Ok(())` if encoding was successful
-    /// * `Err(Error)` if JSON serialization failed
-    fn url_encode_formatter(value: &Value, output: &mut String) -> Result<(), Error> {
-        let object_string = serde_json::to_string(value)?;
-        let encode = urlencoding::encode(object_string.as_str());
-        output.write_str(&encode)?;
+    /// * `Err(TemplateError)` if JSON serialization failed
+    fn url_encode_formatter(value: &Value, output: &mut String) -> Result<(), TemplateError> {
+        let raw = match value {
+            Value::String(text) => Cow::Borrowed(text.as_str()),
+            other => Cow::Owned(serde_json::to_string(other)?),
+        };
+        output.write_str(&urlencoding::encode(&raw))?;
         Ok(())
     }
 
-    pub fn new() -> Self {
-        let mut template = TinyTemplate::new();
-        template.set_default_formatter(&Self::default_formatter);
-        template.add_formatter("url_encode", &Self::url_encode_formatter);
+    /// Quotes a JSON value as a single POSIX shell word.
+    ///
+    /// Strings are used as is and other values as their JSON representation.
+    /// The result is wrapped in single quotes, with embedded single quotes
+    /// written as `'\''`, so the shell never interprets its content.
+    ///
+    /// # Arguments
+    /// * `value` - The JSON value to quote
+    /// * `output` - The output string to write the quoted result to
+    ///
+    /// # Returns
+    /// * `Ok(())` if quoting was successful
+    /// * `Err(TemplateError)` if JSON serialization failed
+    fn shell_escape_formatter(value: &Value, output: &mut String) -> Result<(), TemplateError> {
+        let raw = match value {
+            Value::String(text) => text.clone(),
+            other => serde_json::to_string(other)?,
+        };
+        output.write_str(&Self::shell_quote(&raw))?;
+        Ok(())
+    }
+
+    fn formatter(name: &str) -> Option<Formatter> {
+        match name {
+            "url_encode" => Some(Self::url_encode_formatter),
+            Self::SHELL_ESCAPE_FORMATTER => Some(Self::shell_escape_formatter),
+            _ => None,
+        }
+    }
+
+    pub fn new(mode: TemplateMode) -> Self {
         Self {
-            template,
+            mode,
             templates: HashMap::new(),
         }
     }
 
-    pub fn add_template(&mut self, name: &str, template_str: &str) -> Result<(), Error> {
-        let name_owned = name.to_string();
-        let template_owned = template_str.to_string();
+    fn compile(&self, template_str: &str) -> Result<CompiledTemplate, TemplateError> {
+        match self.mode {
+            TemplateMode::SIMPLE => CompiledTemplate::compile_simple(template_str),
+            TemplateMode::FULL => CompiledTemplate::compile_full(template_str),
+        }
+    }
+
+    pub fn add_template(&mut self, name: &str, template_str: &str) -> Result<(), TemplateError> {
+        let compiled = self.compile(template_str)?;
+        self.templates.insert(name.to_string(), Arc::new(compiled));
+        Ok(())
+    }
+
+    /// Adds a template whose values are all quoted for the shell, unless they already end
+    /// with the `shell_escape` formatter.
+    pub fn add_shell_template(
+        &mut self,
+        name: &str,
+        template_str: &str,
+    ) -> Result<(), TemplateError> {
+        let mut compiled = self.compile(template_str)?;
+        CompiledTemplate::shell_escape_outputs(&mut compiled.nodes);
+        self.templates.insert(name.to_string(), Arc::new(compiled));
+        Ok(())
+    }
 
-        // Use leaked strings for the TinyTemplate and TemplateMap (this is safe for our use case)
-        let name_leak = Box::leak::<'a>(name_owned.into_boxed_str());
-        let template_leak = Box::leak::<'a>(template_owned.into_boxed_str());
+    pub fn render(&self, name: &str, input: &Value) -> Result<String, TemplateError> {
+        self.templates
+            .get(name)
+            .ok_or_else(|| TemplateError::UnknownTemplate(name.to_string()))?
+            .render(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(mode: TemplateMode, text: &str, context: &Value) -> Result<String, TemplateError> {
+        let mut template = Template::new(mode);
+        template.add_template("test", text)?;
+        template.render("test", context)
+    }
+
+    fn simple(text: &str, context: &Value) -> Result<String, TemplateError> {
+        render(TemplateMode::SIMPLE, text, context)
+    }
+
+    #[test]
+    fn simple_placeholders_follow_dotted_and_indexed_paths() {
+        let context = json!({
+            "input": {"location": {"lat": 1.5}, "tags": ["a", "b"], "name": "x"}
+        });
+        assert_eq!(
+            simple(
+                "{input.location.lat},{ input.tags.1 },{input.name}",
+                &context
+            )
+            .unwrap(),
+            "1.5,b,x"
+        );
+        assert!(matches!(
+            simple("{input.tags.2}", &context),
+            Err(TemplateError::NotFound(path)) if path == "input.tags.2"
+        ));
+    }
 
-        // Store the owned strings
-        self.templates.insert(name_leak, template_leak);
+    #[test]
+    fn simple_url_encode_encodes_the_plain_string() {
+        let context = json!({"input": {"q": "hello world", "n": 3, "tags": ["a b"]}});
+        assert_eq!(
+            simple(
+                "q={ input.q | url_encode }&n={input.n|url_encode}",
+                &context
+            )
+            .unwrap(),
+            "q=hello%20world&n=3"
+        );
+        assert_eq!(
+            simple("{input.tags | url_encode}", &context).unwrap(),
+            "%5B%22a%20b%22%5D"
+        );
+    }
+
+    #[test]
+    fn simple_unknown_formatters_fail_to_compile() {
+        let mut template = Template::new(TemplateMode::SIMPLE);
+        assert!(matches!(
+            template.add_template("test", "{input.q | upper}"),
+            Err(TemplateError::UnknownFormatter(name)) if name == "upper"
+        ));
+    }
+
+    #[test]
+    fn simple_shell_escape_quotes_a_single_word() {
+        let context = json!({"input": {"file": "it's; rm -rf /", "n": 42}});
+        assert_eq!(
+            simple("cat {input.file | shell_escape}", &context).unwrap(),
+            r"cat 'it'\''s; rm -rf /'"
+        );
+        assert_eq!(
+            simple("{ input.n | shell_escape }", &context).unwrap(),
+            "'42'"
+        );
+    }
+
+    #[test]
+    fn simple_braces_outside_placeholders_are_literal() {
+        let context = json!({"input": {"name": "x", "n": 1.5}});
+        assert_eq!(
+            simple(
+                r#"{"name": "{ input.name }", "n": {input.n}, "other": {}}"#,
+                &context
+            )
+            .unwrap(),
+            r#"{"name": "x", "n": 1.5, "other": {}}"#
+        );
+        assert!(matches!(
+            simple("{ input.missing }", &context),
+            Err(TemplateError::NotFound(path)) if path == "input.missing"
+        ));
+    }
+
+    #[test]
+    fn shell_templates_escape_placeholders_without_a_formatter() {
+        let context = json!({"input": {"a": "x y", "b": "p q"}});
+        let mut template = Template::new(TemplateMode::SIMPLE);
+        template
+            .add_shell_template("test", "echo { input.a } { input.b | shell_escape }")
+            .unwrap();
+        assert_eq!(
+            template.render("test", &context).unwrap(),
+            "echo 'x y' 'p q'"
+        );
+        assert_eq!(
+            template.unescaped_shell_placeholders(
+                "echo { input.a } { input.b | shell_escape } { input.c | url_encode }"
+            ),
+            vec!["input.a", "input.c"]
+        );
+    }
+
+    #[test]
+    fn templates_compile_once_and_are_shared_by_clones() {
+        let mut template = Template::new(TemplateMode::FULL);
+        template
+            .add_template("greeting", "Hello {{ input.name }}")
+            .unwrap();
+        let clone = template.clone();
+        assert!(Arc::ptr_eq(
+            &template.templates["greeting"],
+            &clone.templates["greeting"]
+        ));
+
+        for name in ["a", "b"] {
+            let context = json!({"input": {"name": name}});
+            assert_eq!(
+                clone.render("greeting", &context).unwrap(),
+                format!("Hello {}", name)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_templates_fail_when_added() {
+        let mut template = Template::new(TemplateMode::FULL);
+        assert!(matches!(
+            template.add_template("broken", "{{ input.name | nope }}"),
+            Err(TemplateError::Parse(_))
+        ));
+        assert!(matches!(
+            template.render("broken", &json!({})),
+            Err(TemplateError::UnknownTemplate(name)) if name == "broken"
+        ));
+    }
+
+    fn full(text: &str, context: &Value) -> Result<String, TemplateError> {
+        render(TemplateMode::FULL, text, context)
+    }
+
+    fn parse_error(text: &str) -> String {
+        let mut template = Template::new(TemplateMode::FULL);
+        match template.add_template("test", text) {
+            Err(TemplateError::Parse(message)) => message,
+            Err(err) => panic!("expected a parse error for {:?}, got {}", text, err),
+            Ok(()) => panic!("expected a parse error for {:?}", text),
+        }
+    }
+
+    #[test]
+    fn full_outputs_values_and_drops_comments() {
+        let context = json!({"input": {"name": "x", "n": 2, "tags": ["a"], "none": null}});
+        assert_eq!(
+            full(
+                "{{ input.name }} {{input.n}} {{ input.tags }} [{{ input.none }}]{# note #}",
+                &context
+            )
+            .unwrap(),
+            r#"x 2 ["a"] []"#
+        );
+        assert_eq!(full("{{ 'lit' }} {{ 3 }}", &context).unwrap(), "lit 3");
+        assert!(matches!(
+            full("{{ input.missing | upper }}", &context),
+            Err(TemplateError::NotFound(path)) if path == "input.missing"
+        ));
+    }
+
+    #[test]
+    fn full_if_picks_the_first_true_branch() {
+        let text = r#"{% if input.a %}a{% elif input.b == "x" %}b{% elif not input.c %}c{% else %}d{% endif %}"#;
+        let cases = [
+            (json!({"a": true}), "a"),
+            (json!({"a": 0, "b": "x"}), "b"),
+            (json!({"b": "y", "c": ""}), "c"),
+            (json!({"c": [1]}), "d"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(full(text, &json!({"input": input})).unwrap(), expected);
+        }
+
+        let text = "{% if input.a != null %}set{% endif %}";
+        assert_eq!(full(text, &json!({"input": {"a": false}})).unwrap(), "set");
+        assert_eq!(full(text, &json!({"input": {"a": null}})).unwrap(), "");
+        assert_eq!(full(text, &json!({"input": {}})).unwrap(), "");
+    }
+
+    #[test]
+    fn full_for_iterates_lists_and_objects() {
+        let context = json!({"input": {"tags": ["a", "b", "c"], "map": {"k": 1}, "none": null}});
+        assert_eq!(
+            full(
+                "{% for tag in input.tags %}{{ loop.index }}{{ tag }}{% if not loop.last %},{% endif %}{% endfor %}",
+                &context
+            )
+            .unwrap(),
+            "1a,2b,3c"
+        );
+        assert_eq!(
+            full(
+                "{% for entry in input.map %}{{ entry.key }}={{ entry.value }}{% endfor %}",
+                &context
+            )
+            .unwrap(),
+            "k=1"
+        );
+        assert_eq!(
+            full(
+                "{% for x in input.none %}x{% endfor %}{% for x in input.missing %}x{% endfor %}",
+                &context
+            )
+            .unwrap(),
+            ""
+        );
+        assert!(matches!(
+            full("{% for x in input.tags.0 %}{% endfor %}", &context),
+            Err(TemplateError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn full_nested_loops_shadow_the_outer_loop() {
+        let context = json!({"input": {"rows": [[1, 2], [3]]}});
+        let text = "{% for row in input.rows %}{{ loop.index0 }}:{% for cell in row %}{{ cell }}/{{ loop.length }}{% if loop.first %}f{% endif %} {% endfor %}{{ loop.length }};{% endfor %}";
+        assert_eq!(full(text, &context).unwrap(), "0:1/2f 2/2 2;1:3/1f 2;");
+        assert!(matches!(
+            full("{% for row in input.rows %}{% endfor %}{{ row }}", &context),
+            Err(TemplateError::NotFound(path)) if path == "row"
+        ));
+    }
+
+    #[test]
+    fn full_filters_transform_values() {
+        let context = json!({
+            "input": {
+                "name": "  Mixed Case ",
+                "tags": ["a", 1, true],
+                "obj": {"a": [1]},
+                "none": null,
+                "time": 0,
+                "rfc": "2024-01-02T03:04:05+01:00",
+                "q": "a b&c",
+                "quote": "it's"
+            }
+        });
+        let cases = [
+            ("{{ input.missing | default('x') }}", "x"),
+            (
+                "{{ input.none | default(input.name) | trim }}",
+                "Mixed Case",
+            ),
+            ("{{ input.tags | join }}", "a1true"),
+            ("{{ input.tags | join(', ') }}", "a, 1, true"),
+            ("{{ input.name | join('-') }}", "  Mixed Case "),
+            ("{{ input.name | upper }}", "  MIXED CASE "),
+            ("{{ input.name | lower | trim }}", "mixed case"),
+            ("{{ input.obj | json }}", r#"{"a":[1]}"#),
+            ("{{ input.q | json }}", r#""a b&c""#),
+            ("{{ 'hi' | base64 }}", "aGk="),
+            (
+                "{{ 'abc' | sha256 }}",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            ("{{ input.time | date }}", "1970-01-01T00:00:00Z"),
+            ("{{ input.rfc | date }}", "2024-01-02T02:04:05Z"),
+            ("{{ input.rfc | date('%Y/%m/%d') }}", "2024/01/02"),
+            ("{{ input.q | url_encode }}", "a%20b%26c"),
+            ("{{ input.quote | shell_escape }}", r"'it'\''s'"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(full(text, &context).unwrap(), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn full_filters_check_their_arguments_and_values() {
+        assert!(parse_error("{{ input.a | nope }}").contains("unknown filter 'nope'"));
+        assert!(parse_error("{{ input.a | default }}").contains("takes 1 argument(s), got 0"));
+        assert!(parse_error("{{ input.a | upper(1) }}").contains("takes 0 argument(s), got 1"));
+        assert!(
+            parse_error("{{ input.a | join(',', '-') }}")
+                .contains("takes 0 to 1 argument(s), got 2")
+        );
+        assert!(parse_error("{{ input.a | date('%Q') }}").contains("invalid date format"));
+        assert!(parse_error("{{ input.a | join(',' }}").contains("expected ',' or ')'"));
+
+        let context = json!({"input": {"flag": true, "text": "soon"}});
+        for text in ["{{ input.flag | date }}", "{{ input.text | date }}"] {
+            assert!(matches!(
+                full(text, &context),
+                Err(TemplateError::Format(_))
+            ));
+        }
+    }
 
-        self.template.add_template(name_leak, template_leak)
+    #[test]
+    fn full_reports_unterminated_and_unexpected_tags() {
+        assert!(parse_error("a {{ input.a").contains("missing '}}' after byte 2"));
+        assert!(parse_error("{% if input.a %}x").contains("missing '{% endif %}'"));
+        assert!(parse_error("{% for x in input.a %}x").contains("missing '{% endfor %}'"));
+        assert!(parse_error("{# note").contains("missing '#}'"));
+        assert!(parse_error("{% if input.a").contains("missing '%}'"));
+        assert!(parse_error("x{% endif %}").contains("unexpected '{% endif %}'"));
+        assert!(parse_error("{% while x %}{% endwhile %}").contains("unexpected '{% while %}'"));
+        assert!(parse_error("{% for x of input.a %}{% endfor %}").contains("expected '<name> in"));
+        assert!(parse_error("{{ 'open }}").contains("unterminated string"));
+        assert!(parse_error("{{ input.a input.b }}").contains("unexpected 'input.b'"));
     }
 
-    pub fn render(&self, name: &str, input: &Value) -> Result<String, Error> {
-        self.template.render(name, input)
+    #[test]
+    fn full_single_braces_in_json_bodies_are_literal() {
+        let context = json!({"input": {"n": 1, "xs": ["a", "b"], "name": "q\"x"}});
+        assert_eq!(
+            full(r#"{"a": {"b": {{ input.n }}}}"#, &context).unwrap(),
+            r#"{"a": {"b": 1}}"#
+        );
+        assert_eq!(
+            full(
+                r#"{"xs": [{% for x in input.xs %}{"v": {{ x | json }}}{% if not loop.last %}, {% endif %}{% endfor %}], "name": {{ input.name | json }}}"#,
+                &context
+            )
+            .unwrap(),
+            r#"{"xs": [{"v": "a"}, {"v": "b"}], "name": "q\"x"}"#
+        );
+        assert_eq!(full("{}", &context).unwrap(), "{}");
     }
 }
@@ -0,0 +1,558 @@
+use crate::core::command::{CommandTool, RenderedCommand};
+use crate::core::config::{Assertion, AssertionTarget, DynamicMCPConfig, ToolTest, ToolType};
+use crate::core::context::TemplateContext;
+use crate::core::http::{HttpTool, RenderedHttpRequest};
+use crate::core::mock::MockServer;
+use crate::core::static_tool::StaticTool;
+use regex::Regex;
+use rmcp::ErrorData;
+use rmcp::model::{CallToolResult, RawContent};
+use serde_json::{Map, Value, json};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Outcome of one test case.
+pub struct TestCase {
+    pub tool: String,
+    pub name: String,
+    pub duration: Duration,
+    pub failures: Vec<String>,
+}
+
+enum ToolUnderTest {
+    Http(HttpTool),
+    Command(CommandTool),
+    Static(StaticTool),
+}
+
+/// Runs the `tests` of every tool.
+///
+/// HTTP tools send every request to a local mock server instead of their upstream, and a request
+/// without a mocked response to answer it fails the test. Commands are never run:
+/// their result is built from the mocked output. Static tools answer without their simulated latency.
+pub async fn run(config: &DynamicMCPConfig) -> std::io::Result<Vec<TestCase>> {
+    let template_context = TemplateContext::new(config);
+    let mock_server = MockServer::start().await?;
+    let default_error_mode = config.error_mode.clone().unwrap_or_default();
+
+    let mut cases = Vec::new();
+    for (i, entry) in config.tools.iter().enumerate() {
+        let Some(ref tests) = entry.tests else {
+            continue;
+        };
+
+        let error_mode = entry
+            .error_mode
+            .clone()
+            .unwrap_or_else(|| default_error_mode.clone());
+        let max_output_bytes = entry.max_output_bytes.or(config.max_output_bytes);
+        let template_mode = entry.template_mode.unwrap_or_default();
+        let tool = match entry.tool_type {
+            ToolType::HTTP => {
+                let Some(ref http_metadata) = entry.http_metadata else {
+                    continue;
+                };
+                ToolUnderTest::Http(
+                    HttpTool::new(
+                        i,
+                        http_metadata.clone(),
+                        error_mode,
+                        max_output_bytes,
+                        None,
+                        template_mode,
+                        None,
+                    )
+                    .with_base_url(mock_server.url()),
+                )
+            }
+            ToolType::COMMAND => {
+                let Some(ref command_metadata) = entry.command_metadata else {
+                    continue;
+                };
+                ToolUnderTest::Command(CommandTool::new(
+                    i,
+                    command_metadata.clone(),
+                    error_mode,
+                    max_output_bytes,
+                    None,
+                    template_mode,
+                ))
+            }
+            ToolType::STATIC => {
+                let Some(ref static_metadata) = entry.static_metadata else {
+                    continue;
+                };
+                ToolUnderTest::Static(StaticTool::new(
+                    i,
+                    static_metadata.clone(),
+                    error_mode,
+                    template_mode,
+                ))
+            }
+        };
+
+        for test in tests.iter() {
+            let started_at = Instant::now();
+            let failures = run_test(&tool, test, &template_context, &mock_server)
+                .await
+                .into_iter()
+                .map(|mut failure| {
+                    template_context.redact(&mut failure);
+                    failure
+                })
+                .collect();
+
+            cases.push(TestCase {
+                tool: entry.name.clone(),
+                name: test.name.clone(),
+                duration: started_at.elapsed(),
+                failures,
+            });
+        }
+    }
+
+    Ok(cases)
+}
+
+/// Returns the failed assertions of a test.
+async fn run_test(
+    tool: &ToolUnderTest,
+    test: &ToolTest,
+    template_context: &TemplateContext,
+    mock_server: &MockServer,
+) -> Vec<String> {
+    let input = Value::Object(test.input.clone().unwrap_or_default());
+    let context = template_context.preview(input);
+
+    let (request, result) = match tool {
+        ToolUnderTest::Http(tool) => match tool.render(&context) {
+            Ok(rendered) => {
+                let request = http_request_value(&rendered);
+                let responses = match test.mock_responses {
+                    Some(ref responses) => responses.clone(),
+                    None => vec![test.mock_response.clone().unwrap_or_default()],
+                };
+                mock_server.respond_with(responses);
+                let result = tool.execute(rendered, None, CancellationToken::new()).await;
+                (Some(request), result)
+            }
+            Err(err) => (None, Err(err)),
+        },
+        ToolUnderTest::Command(tool) => match tool.render(&context) {
+            Ok(rendered) => {
+                let output = test.mock_output.clone().unwrap_or_default();
+                let result = tool.mock_result(
+                    &rendered,
+                    output.exit_code.unwrap_or(0),
+                    output.stdout.as_deref().unwrap_or(""),
+                    output.stderr.as_deref().unwrap_or(""),
+                );
+                (Some(command_value(&rendered)), result)
+            }
+            Err(err) => (None, Err(err)),
+        },
+        ToolUnderTest::Static(tool) => match tool.render(&context) {
+            Ok(reply) => {
+                // Which rule answered stands in for the request
+                let request = json!({ "rule": reply.rule.map(|idx| idx + 1) });
+                (Some(request), tool.result(reply))
+            }
+            Err(err) => (None, Err(err)),
+        },
+    };
+
+    let unexpected = match tool {
+        ToolUnderTest::Http(_) => mock_server.unexpected_requests(),
+        _ => vec![],
+    };
+    unexpected
+        .into_iter()
+        .map(|request| format!("unexpected request {}", request))
+        .chain(
+            test.assertions
+                .iter()
+                .enumerate()
+                .filter_map(|(index, assertion)| {
+                    check(assertion, request.as_ref(), &result)
+                        .err()
+                        .map(|failure| format!("assertion {}: {}", index + 1, failure))
+                }),
+        )
+        .collect()
+}
+
+/// Body of a request as JSON when it is valid JSON, as text otherwise.
+fn body_value(body: &str) -> Value {
+    serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string()))
+}
+
+fn http_request_value(request: &RenderedHttpRequest) -> Value {
+    let headers: Map<String, Value> = request
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                Value::String(value.to_str().unwrap_or("").to_string()),
+            )
+        })
+        .collect();
+
+    json!({
+        "method": format!("{:?}", request.method),
+        "url": request.url,
+        "headers": headers,
+        "body": request.body.as_deref().map(body_value),
+    })
+}
+
+fn command_value(command: &RenderedCommand) -> Value {
+    let env: Map<String, Value> = command
+        .env
+        .iter()
+        .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+        .collect();
+
+    json!({
+        "command": command.command,
+        "args": command.args,
+        "stdin": command.stdin,
+        "env": env,
+    })
+}
+
+fn result_value(result: &Result<CallToolResult, ErrorData>) -> Value {
+    match result {
+        Ok(result) => serde_json::to_value(result),
+        Err(err) => serde_json::to_value(err),
+    }
+    .unwrap_or(Value::Null)
+}
+
+/// Text contents of a result joined by newlines, or the message of a protocol error.
+fn result_text(result: &Result<CallToolResult, ErrorData>) -> String {
+    match result {
+        Ok(result) => result
+            .content
+            .iter()
+            .flatten()
+            .filter_map(|content| match content.raw {
+                RawContent::Text(ref text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<&str>>()
+            .join("\n"),
+        Err(err) => err.message.to_string(),
+    }
+}
+
+fn check(
+    assertion: &Assertion,
+    request: Option<&Value>,
+    result: &Result<CallToolResult, ErrorData>,
+) -> Result<(), String> {
+    let (subject, name) = match assertion.target {
+        AssertionTarget::REQUEST => match request {
+            Some(request) => (request.clone(), "request"),
+            None => {
+                return Err(format!("no request was rendered: {}", result_text(result)));
+            }
+        },
+        AssertionTarget::RESULT => (result_value(result), "result"),
+        AssertionTarget::TEXT => (Value::String(result_text(result)), "text"),
+    };
+
+    let (subject, name) = match assertion.path {
+        Some(ref path) => {
+            let root = match subject {
+                Value::String(text) if assertion.target == AssertionTarget::TEXT => {
+                    serde_json::from_str(&text)
+                        .map_err(|err| format!("text is not JSON: {}", err))?
+                }
+                other => other,
+            };
+            let value = json_path(&root, path)?
+                .cloned()
+                .ok_or_else(|| format!("{} {} matches nothing", name, path))?;
+            (value, format!("{} {}", name, path))
+        }
+        None => (subject, name.to_string()),
+    };
+
+    if assertion.equals.is_none() && assertion.matches.is_none() {
+        return Err("set equals or matches".to_string());
+    }
+
+    if let Some(ref expected) = assertion.equals
+        && subject != *expected
+    {
+        return Err(format!(
+            "expected {} to equal {}, got {}",
+            name, expected, subject
+        ));
+    }
+
+    if let Some(ref pattern) = assertion.matches {
+        let regex =
+            Regex::new(pattern).map_err(|err| format!("invalid regex {}: {}", pattern, err))?;
+        let text = match subject {
+            Value::String(ref text) => text.clone(),
+            ref other => other.to_string(),
+        };
+        if !regex.is_match(&text) {
+            return Err(format!(
+                "expected {} to match {}, got {}",
+                name, pattern, subject
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Looks up a JSONPath such as `$.items[0].name` or `$['content-type']`.
+///
+/// Only the root, child and array index selectors are supported.
+fn json_path<'v>(value: &'v Value, path: &str) -> Result<Option<&'v Value>, String> {
+    let invalid = || format!("unsupported JSONPath {}", path);
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut current = Some(value);
+
+    while !rest.is_empty() {
+        let (step, remaining) = if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            (after[..end].to_string(), &after[end..])
+        } else if let Some(after) = rest.strip_prefix("['") {
+            let end = after.find("']").ok_or_else(invalid)?;
+            (after[..end].to_string(), &after[end + 2..])
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            let index = after[..end].parse::<usize>().map_err(|_| invalid())?;
+            current = current.and_then(|value| value.get(index));
+            rest = &after[end + 1..];
+            continue;
+        } else {
+            return Err(invalid());
+        };
+
+        if step.is_empty() || step == "*" {
+            return Err(invalid());
+        }
+        current = current.and_then(|value| value.get(&step));
+        rest = remaining;
+    }
+
+    Ok(current)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Writes a JUnit XML report with one test suite per tool.
+pub fn junit_xml(cases: &[TestCase]) -> String {
+    let failures = |cases: &[&TestCase]| {
+        cases
+            .iter()
+            .filter(|case| !case.failures.is_empty())
+            .count()
+    };
+    let seconds = |cases: &[&TestCase]| {
+        cases
+            .iter()
+            .map(|case| case.duration.as_secs_f64())
+            .sum::<f64>()
+    };
+
+    let mut tools: Vec<&str> = Vec::new();
+    for case in cases.iter() {
+        if !tools.contains(&case.tool.as_str()) {
+            tools.push(&case.tool);
+        }
+    }
+
+    let all: Vec<&TestCase> = cases.iter().collect();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"easymcp\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        all.len(),
+        failures(&all),
+        seconds(&all)
+    ));
+
+    for tool in tools {
+        let suite: Vec<&TestCase> = cases.iter().filter(|case| case.tool == tool).collect();
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(tool),
+            suite.len(),
+            failures(&suite),
+            seconds(&suite)
+        ));
+
+        for case in suite {
+            let attributes = format!(
+                "name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                xml_escape(&case.name),
+                xml_escape(&case.tool),
+                case.duration.as_secs_f64()
+            );
+            match case.failures.first() {
+                None => xml.push_str(&format!("    <testcase {} />\n", attributes)),
+                Some(first) => {
+                    xml.push_str(&format!("    <testcase {}>\n", attributes));
+                    xml.push_str(&format!(
+                        "      <failure message=\"{}\">{}</failure>\n",
+                        xml_escape(first),
+                        xml_escape(&case.failures.join("\n"))
+                    ));
+                    xml.push_str("    </testcase>\n");
+                }
+            }
+        }
+
+        xml.push_str("  </testsuite>\n");
+    }
+
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::Content;
+
+    fn assertion(assertion: Value) -> Assertion {
+        serde_json::from_value(assertion).unwrap()
+    }
+
+    #[test]
+    fn json_path_follows_children_and_indexes() {
+        let value = json!({
+            "a": {"b": [{"c": 1}, {"c": 2}]},
+            "headers": {"content-type": "text/plain"},
+            "list": [[1, 2], [3]]
+        });
+        assert_eq!(json_path(&value, "$").unwrap(), Some(&value));
+        assert_eq!(
+            json_path(&value, "$.a.b[0]").unwrap(),
+            Some(&json!({"c": 1}))
+        );
+        assert_eq!(json_path(&value, "$.a.b[1].c").unwrap(), Some(&json!(2)));
+        assert_eq!(json_path(&value, "$.list[1][0]").unwrap(), Some(&json!(3)));
+        assert_eq!(
+            json_path(&value, "$.headers['content-type']").unwrap(),
+            Some(&json!("text/plain"))
+        );
+        assert_eq!(
+            json_path(&value, "$['headers'].content-type").unwrap(),
+            Some(&json!("text/plain"))
+        );
+    }
+
+    #[test]
+    fn json_path_matches_nothing_for_missing_keys_and_indexes() {
+        let value = json!({"a": {"b": [1]}});
+        for path in [
+            "$.missing",
+            "$.a.missing.b",
+            "$.a.b[1]",
+            "$.a.b[0].c",
+            "$.a[0]",
+        ] {
+            assert_eq!(json_path(&value, path).unwrap(), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn json_path_rejects_unsupported_syntax() {
+        let value = json!({"a": [1]});
+        for path in [
+            "a.b", "$..a", "$.a[*]", "$.*", "$.a[-1]", "$['a'", "$.a[0", "$a",
+        ] {
+            assert_eq!(
+                json_path(&value, path),
+                Err(format!("unsupported JSONPath {}", path)),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn check_reports_paths_that_match_nothing() {
+        let result = Ok(CallToolResult::success(vec![Content::text(r#"{"a": 1}"#)]));
+
+        let found = assertion(json!({"target": "TEXT", "path": "$.a", "equals": 1}));
+        assert_eq!(check(&found, None, &result), Ok(()));
+
+        let missing = assertion(json!({"target": "TEXT", "path": "$.b", "equals": 1}));
+        assert_eq!(
+            check(&missing, None, &result),
+            Err("text $.b matches nothing".to_string())
+        );
+
+        let differs = assertion(json!({"target": "TEXT", "path": "$.a", "equals": 2}));
+        assert_eq!(
+            check(&differs, None, &result),
+            Err("expected text $.a to equal 2, got 1".to_string())
+        );
+    }
+
+    fn config(yaml: &str) -> DynamicMCPConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const PAGINATED_TOOL: &str = r#"
+tools:
+  - name: items
+    description: items
+    tool_type: HTTP
+    http_metadata:
+      url: "https://api.example.com/items"
+      method: GET
+      input_schema: {}
+      pagination:
+        strategy: LINK_HEADER
+    tests:
+      - name: two pages
+        mock_responses:
+          - headers:
+              Content-Type: application/json
+              Link: '<https://api.example.com/items?page=2>; rel="next"'
+            body: '[1, 2]'
+          - headers: {Content-Type: application/json}
+            body: '[3]'
+        assertions:
+          - {target: text, path: "$", equals: [1, 2, 3]}
+      - name: missing page
+        mock_response:
+          headers:
+            Content-Type: application/json
+            Link: '<https://api.example.com/items?page=2>; rel="next"'
+          body: '[1, 2]'
+        assertions: []
+"#;
+
+    #[tokio::test]
+    async fn every_page_is_answered_by_the_mock_server() {
+        let cases = run(&config(PAGINATED_TOOL)).await.unwrap();
+        assert_eq!(cases[0].name, "two pages");
+        assert!(cases[0].failures.is_empty(), "{:?}", cases[0].failures);
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_mocked_response_fail_the_test() {
+        let cases = run(&config(PAGINATED_TOOL)).await.unwrap();
+        assert_eq!(cases[1].name, "missing page");
+        assert_eq!(
+            cases[1].failures,
+            vec!["unexpected request GET /items?page=2"]
+        );
+    }
+}
@@ -1,24 +1,150 @@
 mod core;
 
-use clap::Parser;
-use core::config::{DynamicMCPConfig, TransportType};
+use clap::{Parser, Subcommand};
+use core::config::{CassetteConfig, CassetteMode, DynamicMCPConfig, OpenApiSource, TransportType};
 use duration_string::DurationString;
-use rmcp::ServiceExt;
+use rmcp::model::{CallToolRequestParam, ClientInfo, Implementation, JsonObject};
+use rmcp::service::{RunningService, ServiceError};
 use rmcp::transport::sse_server::SseServerConfig;
 use rmcp::transport::{SseServer, stdio};
+use rmcp::{RoleClient, ServiceExt};
+use serde_json::Value;
 use tokio_util::sync::CancellationToken;
 
 #[derive(Parser, Debug)]
 struct Args {
-    #[clap(short = 'f', long = "file_path", help = "File path to the yaml file")]
-    file_path: String,
+    #[clap(
+        short = 'f',
+        long = "file_path",
+        global = true,
+        help = "File path to the yaml file"
+    )]
+    file_path: Option<String>,
+
+    #[clap(
+        long = "record",
+        global = true,
+        conflicts_with = "replay",
+        help = "Directory to record every upstream HTTP request and response to"
+    )]
+    record: Option<String>,
+
+    #[clap(
+        long = "replay",
+        global = true,
+        help = "Directory of recorded responses to answer upstream HTTP requests from, without network"
+    )]
+    replay: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check the config file and report warnings without starting the server
+    Validate,
+    /// Print the HTTP request or command line a tool call would make, without making it
+    Render {
+        #[clap(long = "tool", help = "Name of the tool to render")]
+        tool: String,
+
+        #[clap(
+            long = "input",
+            default_value = "{}",
+            help = "Tool input as a JSON object"
+        )]
+        input: String,
+    },
+    /// Call a tool and print its result as JSON
+    Call {
+        #[clap(help = "Name of the tool to call")]
+        tool: String,
+
+        #[clap(
+            long = "input",
+            default_value = "{}",
+            help = "Tool input as a JSON object"
+        )]
+        input: String,
+    },
+    /// Print the tools of the config file as JSON
+    ListTools,
+    /// Run the tests of the config file against mocked upstreams
+    Test {
+        #[clap(long = "junit", help = "File path to write a JUnit XML report to")]
+        junit: Option<String>,
+    },
+    /// Print tools generated from an API description as a config file
+    Import {
+        #[clap(subcommand)]
+        source: ImportSource,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ImportSource {
+    /// Generate an HTTP tool per operation of an OpenAPI 3 specification
+    Openapi {
+        #[clap(help = "File path to the specification, in YAML or JSON")]
+        spec: String,
+
+        #[clap(long = "base-url", help = "URL the operation paths are appended to")]
+        base_url: Option<String>,
+
+        #[clap(long = "include-tag", help = "Only import operations with this tag")]
+        include_tags: Vec<String>,
+
+        #[clap(long = "exclude-tag", help = "Skip operations with this tag")]
+        exclude_tags: Vec<String>,
+
+        #[clap(
+            long = "include-path",
+            help = "Only import paths matching this pattern, where * matches anything"
+        )]
+        include_paths: Vec<String>,
+
+        #[clap(
+            long = "exclude-path",
+            help = "Skip paths matching this pattern, where * matches anything"
+        )]
+        exclude_paths: Vec<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let config = DynamicMCPConfig::new_from_file(args.file_path.clone()).await;
+    if let Some(Command::Import { source }) = args.command {
+        return import(source).await;
+    }
+
+    let Some(file_path) = args.file_path else {
+        panic!("--file_path is required");
+    };
+
+    let mut config = DynamicMCPConfig::new_from_file(file_path).await;
+    config.cassette = match (args.record, args.replay) {
+        (Some(dir), _) => Some(CassetteConfig {
+            mode: CassetteMode::RECORD,
+            dir,
+        }),
+        (None, Some(dir)) => Some(CassetteConfig {
+            mode: CassetteMode::REPLAY,
+            dir,
+        }),
+        (None, None) => None,
+    };
+
+    match args.command {
+        Some(Command::Validate) => return validate(config),
+        Some(Command::Render { tool, input }) => return render(config, &tool, &input),
+        Some(Command::Call { tool, input }) => return call(config, &tool, &input).await,
+        Some(Command::ListTools) => return list_tools(config).await,
+        Some(Command::Test { junit }) => return test(config, junit).await,
+        Some(Command::Import { .. }) | None => {}
+    }
 
     let Some(ref transport_config) = config.transport_config else {
         panic!("transport_config is required");
@@ -72,7 +198,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             println!("Server listening on {}", sse_server.config.bind);
 
-            let ct = sse_server.with_service(move || core::engine::DynamicMCP::new(config.clone()));
+            // Build the tools once so sessions share state such as response caches and limits
+            let service = core::engine::DynamicMCP::new(config.clone());
+            let ct = sse_server.with_service(move || service.new_session());
 
             tokio::signal::ctrl_c().await?;
             ct.cancel();
@@ -81,3 +209,257 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Builds the tools, which panics on invalid tool configs, and prints the warnings.
+fn check(config: &DynamicMCPConfig) -> Vec<String> {
+    // Building the tools registers every template
+    core::engine::DynamicMCP::new(config.clone()).new_session();
+
+    let warnings = core::engine::DynamicMCP::validation_warnings(config);
+    for warning in warnings.iter() {
+        eprintln!("warning: {}", warning);
+    }
+    warnings
+}
+
+fn validate(config: DynamicMCPConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let warnings = check(&config);
+
+    println!(
+        "Config is valid: {} tools, {} warnings",
+        config.tools.len(),
+        warnings.len()
+    );
+
+    Ok(())
+}
+
+fn render(
+    config: DynamicMCPConfig,
+    tool: &str,
+    input: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let input: Value = serde_json::from_str(input)
+        .unwrap_or_else(|err| panic!("Error while parsing --input as JSON: {}", err));
+
+    check(&config);
+
+    match core::engine::DynamicMCP::render_preview(&config, tool, input) {
+        Ok(preview) => println!("{}", preview),
+        Err(err) => {
+            eprintln!("Error while rendering tool {}: {}", tool, err);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// Serves the tools to an in-process client, so calls from the command line take the same path
+/// as MCP requests, limits included.
+async fn connect(
+    config: DynamicMCPConfig,
+) -> Result<RunningService<RoleClient, ClientInfo>, Box<dyn std::error::Error>> {
+    let (server_stream, client_stream) = tokio::io::duplex(64 * 1024);
+
+    let service = core::engine::DynamicMCP::new(config).new_session();
+    tokio::spawn(async move {
+        match service.serve(tokio::io::split(server_stream)).await {
+            Ok(server) => {
+                let _ = server.waiting().await;
+            }
+            Err(err) => eprintln!("Error while starting the service: {}", err),
+        }
+    });
+
+    let client_info = ClientInfo {
+        client_info: Implementation {
+            name: "easymcp".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+        ..Default::default()
+    };
+    Ok(client_info.serve(tokio::io::split(client_stream)).await?)
+}
+
+async fn call(
+    config: DynamicMCPConfig,
+    tool: &str,
+    input: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let arguments: JsonObject = serde_json::from_str(input)
+        .unwrap_or_else(|err| panic!("Error while parsing --input as a JSON object: {}", err));
+
+    let client = connect(config).await?;
+    let result = client
+        .call_tool(CallToolRequestParam {
+            name: tool.to_string().into(),
+            arguments: Some(arguments),
+        })
+        .await;
+    client.cancel().await?;
+
+    match result {
+        Ok(result) => {
+            println!("{}", serde_json::to_string_pretty(&result)?);
+            if result.is_error == Some(true) {
+                std::process::exit(1);
+            }
+        }
+        Err(ServiceError::McpError(err)) => {
+            eprintln!("Error while calling tool {}: {}", tool, err.message);
+            std::process::exit(1);
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(())
+}
+
+async fn list_tools(config: DynamicMCPConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let client = connect(config).await?;
+    let tools = client.list_all_tools().await;
+    client.cancel().await?;
+
+    let mut tools = tools?;
+    tools.sort_by(|a, b| a.name.cmp(&b.name));
+    println!("{}", serde_json::to_string_pretty(&tools)?);
+    Ok(())
+}
+
+async fn test(
+    config: DynamicMCPConfig,
+    junit: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check(&config);
+
+    let cases = core::testing::run(&config).await?;
+    for case in cases.iter() {
+        match case.failures.is_empty() {
+            true => println!("PASS {} / {}", case.tool, case.name),
+            false => {
+                println!("FAIL {} / {}", case.tool, case.name);
+                for failure in case.failures.iter() {
+                    println!("  {}", failure);
+                }
+            }
+        }
+    }
+
+    let failed = cases
+        .iter()
+        .filter(|case| !case.failures.is_empty())
+        .count();
+    println!("{} passed, {} failed", cases.len() - failed, failed);
+
+    if let Some(junit) = junit {
+        std::fs::write(&junit, core::testing::junit_xml(&cases))
+            .unwrap_or_else(|err| panic!("Error while writing the JUnit report: {}", err));
+    }
+
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn import(source: ImportSource) -> Result<(), Box<dyn std::error::Error>> {
+    let ImportSource::Openapi {
+        spec,
+        base_url,
+        include_tags,
+        exclude_tags,
+        include_paths,
+        exclude_paths,
+    } = source;
+    let filter = |values: Vec<String>| (!values.is_empty()).then_some(values);
+    let source = OpenApiSource {
+        path: spec,
+        base_url,
+        headers: None,
+        include_tags: filter(include_tags),
+        exclude_tags: filter(exclude_tags),
+        include_paths: filter(include_paths),
+        exclude_paths: filter(exclude_paths),
+    };
+
+    let (tools, warnings) = match core::openapi::import(&source).await {
+        Ok(imported) => imported,
+        Err(err) => {
+            eprintln!("Error while importing {}: {}", source.path, err);
+            std::process::exit(1);
+        }
+    };
+    for warning in warnings.iter() {
+        eprintln!("warning: {}", warning);
+    }
+
+    let mut config = serde_yaml::Mapping::new();
+    config.insert("tools".into(), serde_yaml::Value::Sequence(tools));
+    print!("{}", serde_yaml::to_string(&config)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_process_client_lists_and_calls_tools() {
+        let config: DynamicMCPConfig = serde_yaml::from_str(
+            r#"
+tools:
+  - name: greet
+    description: greet
+    tool_type: COMMAND
+    command_metadata:
+      command: echo
+      args: ["hello", "{ input.name }"]
+      input_schema:
+        type: object
+  - name: date
+    description: date
+    tool_type: COMMAND
+    command_metadata:
+      command: date
+      input_schema:
+        type: object
+"#,
+        )
+        .unwrap();
+        let client = connect(config).await.unwrap();
+
+        let mut tools = client.list_all_tools().await.unwrap();
+        tools.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<&str> = tools.iter().map(|tool| tool.name.as_ref()).collect();
+        assert_eq!(names, vec!["date", "greet"]);
+
+        let result = client
+            .call_tool(CallToolRequestParam {
+                name: "greet".into(),
+                arguments: serde_json::json!({"name": "world"}).as_object().cloned(),
+            })
+            .await
+            .unwrap();
+        assert_ne!(result.is_error, Some(true));
+        assert_eq!(
+            result.content.as_ref().unwrap()[0]
+                .as_text()
+                .unwrap()
+                .text
+                .trim_end(),
+            "hello world"
+        );
+
+        let err = client
+            .call_tool(CallToolRequestParam {
+                name: "missing".into(),
+                arguments: None,
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ServiceError::McpError(_)), "{:?}", err);
+        client.cancel().await.unwrap();
+    }
+}